@@ -0,0 +1,43 @@
+use crate::MemoFormat;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+pub const CONFIG_FILE_NAME: &str = ".gitmemo.toml";
+
+// defaults read from .gitmemo.toml at the repository root
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub repo: Option<String>,
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub format: Option<MemoFormat>,
+    pub abbrev: Option<usize>,
+    // globs of memo file paths which are not tracked
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+impl Config {
+    // a missing config file is the same as an empty one
+    pub fn load(repo_root: &str) -> Result<Config> {
+        let path = Path::new(repo_root).join(CONFIG_FILE_NAME);
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut config: Config = toml::from_str(&text)
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+        // relative paths are taken from the directory of the config file
+        for value in [&mut config.repo, &mut config.input, &mut config.output]
+            .into_iter()
+            .flatten()
+        {
+            if value != "-" && Path::new(value.as_str()).is_relative() {
+                *value = Path::new(repo_root).join(&value).to_string_lossy().into();
+            }
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,18 @@
+use thiserror::Error;
+
+// the root cause of errors of the library, which stay anyhow::Error so that
+// context can be added; match on it with err.downcast_ref::<GitMemoError>()
+#[derive(Error, Debug)]
+pub enum GitMemoError {
+    #[error("git executable {binary} is not found")]
+    GitNotFound { binary: String },
+    #[error("Failed to run git command: exit_code={exit_code}, stderr={stderr}")]
+    BlameFailed { exit_code: i32, stderr: String },
+    #[error("Failed to resolve revision {revision}: {stderr}")]
+    RevisionNotFound { revision: String, stderr: String },
+    // the message names the memo file, the location and the field
+    #[error("{0}")]
+    ParseError(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
@@ -0,0 +1,4135 @@
+use anyhow::{anyhow, Context, Result};
+use error::GitMemoError;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use regex::Regex;
+use runner::{GitRunner, SystemGit};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::io::Write;
+use std::io::{BufRead, BufReader};
+use std::path::{Component, Path};
+use std::process::Command;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[macro_use]
+extern crate serde_derive;
+
+#[macro_use]
+extern crate derive_builder;
+
+// step logging of tracking, shown with --verbose
+macro_rules! verbose {
+    ($option:expr, $($arg:tt)*) => {
+        if $option.verbose {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// warnings and notes, silenced by -q
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if !$crate::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+pub mod config;
+pub mod error;
+pub mod notes;
+pub mod runner;
+pub mod store;
+
+use strum_macros::{AsRefStr, Display, EnumString};
+// serde and strum both use the variant names, so the memo files, the parsed
+// strings and the printed statuses agree without renames on either side
+#[derive(EnumString, Display, AsRefStr, Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum TagStatus {
+    Normal,
+    Missing,
+    // the revision is not found in the repository
+    Unknown,
+    // re-anchored by --fuzz to a similar line near where blame lost it
+    Fuzzy,
+    // the line has changes which are not committed yet (the all-zero revision)
+    Uncommitted,
+}
+
+impl TagStatus {
+    // Normal and Fuzzy tags point at a line which is tracked further
+    pub fn is_anchored(&self) -> bool {
+        matches!(self, TagStatus::Normal | TagStatus::Fuzzy)
+    }
+}
+
+// git blame attributes lines which are not committed yet to the all-zero revision
+pub const ZERO_REVISION: &str = "0000000000000000000000000000000000000000";
+
+pub fn is_zero_revision(revision: &str) -> bool {
+    !revision.is_empty() && revision.bytes().all(|byte| byte == b'0')
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommentTag {
+    pub revision: String,
+    pub line: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_line: Option<i32>,
+    pub status: TagStatus,
+    // hash of the trimmed first line when the tag was stamped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    // the last change to the line at the revision, recorded with --record-author
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committed_at: Option<String>,
+    // when the tracking run which pushed the tag advanced it, in UTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracked_at: Option<String>,
+    // the definition around the line, added with --symbol
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<SymbolAnchor>,
+}
+
+// "line offset within function name", which relocates a memo whose
+// line blame loses as long as the definition is found at the target
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SymbolAnchor {
+    pub name: String,
+    // lines from the line of the definition
+    pub offset: i32,
+}
+
+// the line of a memo on the whole file, which is never tracked by blame
+pub const FILE_LEVEL_LINE: i32 = 0;
+
+impl CommentTag {
+    pub fn is_file_level(&self) -> bool {
+        self.line == FILE_LEVEL_LINE
+    }
+
+    // "start" for a single line and "start-end" for a range
+    pub fn line_span(&self) -> String {
+        match self.end_line {
+            Some(end_line) if end_line != self.line => format!("{}-{}", self.line, end_line),
+            _ => self.line.to_string(),
+        }
+    }
+
+    // revisions may be abbreviated to different lengths
+    pub fn is_same_position(&self, other: &CommentTag) -> bool {
+        (self.revision.starts_with(&other.revision) || other.revision.starts_with(&self.revision))
+            && self.line == other.line
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Comment {
+    pub text: String,
+    pub tags: Vec<CommentTag>,
+    // user fields such as priority or ticket, left as they are by tracking
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileData {
+    pub path: String,
+    // repository of the file when it is not --repo, relative to --repo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    pub comments: Vec<Comment>,
+}
+
+impl FileData {
+    pub fn repo_path(&self, target_repo: &str) -> String {
+        match &self.repo {
+            Some(repo) => Path::new(target_repo).join(repo).to_string_lossy().into(),
+            None => target_repo.to_string(),
+        }
+    }
+}
+
+// bumped whenever older memo files need rewriting on load
+pub const ROOT_DATA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RootData {
+    // files written before versioning have no version (0)
+    #[serde(default, serialize_with = "serialize_current_version")]
+    pub version: u32,
+    pub files: Vec<FileData>,
+}
+
+fn serialize_current_version<S: serde::Serializer>(
+    _version: &u32,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_u32(ROOT_DATA_VERSION)
+}
+
+// upgrade documents written by earlier releases
+pub fn migrate_root_data(mut data: RootData) -> Result<RootData> {
+    if data.version > ROOT_DATA_VERSION {
+        return Err(anyhow!(
+            "memo file version {} is newer than the supported version {}",
+            data.version,
+            ROOT_DATA_VERSION
+        ));
+    }
+    // 0 -> 1: end_line and content_hash are optional, so tags load as they are
+    data.version = ROOT_DATA_VERSION;
+    Ok(data)
+}
+
+// comments of the same path and repo are concatenated in the order of the
+// parts; the index of the part each comment came from is returned per file
+pub fn merge_root_data(parts: Vec<RootData>) -> (RootData, Vec<Vec<usize>>) {
+    let mut data = RootData {
+        version: ROOT_DATA_VERSION,
+        files: Vec::new(),
+    };
+    let mut origins: Vec<Vec<usize>> = Vec::new();
+    for (part_index, part) in parts.into_iter().enumerate() {
+        for file_data in part.files {
+            let origin = vec![part_index; file_data.comments.len()];
+            match data
+                .files
+                .iter()
+                .position(|merged| merged.path == file_data.path && merged.repo == file_data.repo)
+            {
+                Some(index) => {
+                    data.files[index].comments.extend(file_data.comments);
+                    origins[index].extend(origin);
+                }
+                None => {
+                    data.files.push(file_data);
+                    origins.push(origin);
+                }
+            }
+        }
+    }
+    (data, origins)
+}
+
+// the inverse of merge_root_data, for data whose files and comments are still
+// in the merged order, as tracking keeps them
+pub fn split_root_data(
+    data: RootData,
+    origins: &[Vec<usize>],
+    parts: usize,
+) -> Result<Vec<RootData>> {
+    if data.files.len() != origins.len() {
+        return Err(anyhow!(
+            "{} files cannot be split by the origins of {} files",
+            data.files.len(),
+            origins.len()
+        ));
+    }
+    let mut split: Vec<RootData> = (0..parts)
+        .map(|_| RootData {
+            version: data.version,
+            files: Vec::new(),
+        })
+        .collect();
+    for (file_data, origin) in data.files.into_iter().zip(origins) {
+        if file_data.comments.len() != origin.len() {
+            return Err(anyhow!(
+                "{} memos of {} cannot be split by the origins of {} memos",
+                file_data.comments.len(),
+                file_data.path,
+                origin.len()
+            ));
+        }
+        for (comment, part_index) in file_data.comments.into_iter().zip(origin) {
+            let part = &mut split[*part_index];
+            match part.files.last_mut() {
+                Some(last) if last.path == file_data.path && last.repo == file_data.repo => {
+                    last.comments.push(comment)
+                }
+                _ => part.files.push(FileData {
+                    path: file_data.path.clone(),
+                    repo: file_data.repo.clone(),
+                    comments: vec![comment],
+                }),
+            }
+        }
+    }
+    Ok(split)
+}
+
+// line numbers are 1-based, which serde cannot check for an i32,
+// except for FILE_LEVEL_LINE
+pub fn validate_root_data(data: &RootData) -> Result<()> {
+    for (file_index, file_data) in data.files.iter().enumerate() {
+        for (comment_index, comment) in file_data.comments.iter().enumerate() {
+            for (tag_index, tag) in comment.tags.iter().enumerate() {
+                let problem = if tag.is_file_level() {
+                    match tag.end_line {
+                        Some(end_line) => {
+                            format!(
+                                "end_line {} is given for a memo on the whole file",
+                                end_line
+                            )
+                        }
+                        None => continue,
+                    }
+                } else if tag.line < 1 {
+                    format!("line {} is not a positive line number", tag.line)
+                } else if let Some(end_line) = tag.end_line.filter(|end_line| *end_line < tag.line)
+                {
+                    format!("end_line {} is before line {}", end_line, tag.line)
+                } else {
+                    continue;
+                };
+                return Err(anyhow!(
+                    "{} (field files[{}].comments[{}].tags[{}], memo {:?} of {})",
+                    problem,
+                    file_index,
+                    comment_index,
+                    tag_index,
+                    comment.text,
+                    file_data.path
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct GitSettings {
+    // path or name of the git executable
+    pub binary: String,
+    // git is killed when it runs longer
+    pub timeout: Option<Duration>,
+    // times a git command which failed to start or timed out is run again
+    pub retries: usize,
+}
+
+impl Default for GitSettings {
+    fn default() -> GitSettings {
+        GitSettings {
+            binary: String::from("git"),
+            timeout: None,
+            retries: 0,
+        }
+    }
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+// set once from the command line before any git command runs
+static GIT_SETTINGS: OnceLock<GitSettings> = OnceLock::new();
+
+pub fn configure_git(settings: GitSettings) {
+    // the first setting wins
+    let _ = GIT_SETTINGS.set(settings);
+}
+
+fn git_settings() -> &'static GitSettings {
+    GIT_SETTINGS.get_or_init(GitSettings::default)
+}
+
+pub fn git_command() -> Command {
+    Command::new(&git_settings().binary)
+}
+
+// a failed git command is not retried since running it again gives the same result
+fn retry_git<T>(mut run: impl FnMut() -> Result<T>) -> Result<T> {
+    let settings = git_settings();
+    let mut attempt = 0;
+    loop {
+        match run() {
+            Ok(output) => return Ok(output),
+            Err(err) if attempt < settings.retries => {
+                attempt += 1;
+                warning!(
+                    "warning: {:#}, retrying ({}/{})",
+                    err,
+                    attempt,
+                    settings.retries
+                );
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// stdout is handed to consume as it is written, so that it is never buffered
+// as a whole; stderr is collected and returned with the exit status; input is
+// written to stdin, which the command has to pipe then
+fn stream_git<T>(
+    command: &mut Command,
+    name: &str,
+    input: Option<&[u8]>,
+    consume: impl FnOnce(&mut dyn BufRead) -> Result<T>,
+) -> Result<(ExitStatus, Vec<u8>, T)> {
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Err(GitMemoError::GitNotFound {
+                binary: git_settings().binary.clone(),
+            })
+            .with_context(|| format!("failed to spawn {}", name));
+        }
+        Err(err) => {
+            return Err(GitMemoError::Io(err)).with_context(|| format!("failed to spawn {}", name))
+        }
+    };
+    // written meanwhile, or git may block on a full stdout pipe before reading all of it
+    let stdin_pipe = child.stdin.take();
+    let stdin = input.map(|input| {
+        let input = input.to_vec();
+        std::thread::spawn(move || match stdin_pipe {
+            Some(mut pipe) => pipe.write_all(&input),
+            None => Ok(()),
+        })
+    });
+    // stderr is drained meanwhile, or git blocks once its pipe is full
+    let stderr_pipe = child.stderr.take();
+    let stderr = std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = stderr_pipe {
+            pipe.read_to_end(&mut buffer)?;
+        }
+        Ok::<Vec<u8>, std::io::Error>(buffer)
+    });
+    let stdout_pipe = child.stdout.take();
+    let child = Arc::new(Mutex::new(child));
+    // the watchdog kills git at the deadline unless it is told that git is done
+    let (done, watchdog) = match git_settings().timeout {
+        Some(timeout) => {
+            let (done, done_receiver) = mpsc::channel::<()>();
+            let child = Arc::clone(&child);
+            let watchdog = std::thread::spawn(move || {
+                if done_receiver.recv_timeout(timeout) != Err(RecvTimeoutError::Timeout) {
+                    return false;
+                }
+                let mut child = child.lock().unwrap_or_else(|err| err.into_inner());
+                if matches!(child.try_wait(), Ok(Some(_))) {
+                    return false;
+                }
+                let _ = child.kill();
+                true
+            });
+            (Some(done), Some((watchdog, timeout)))
+        }
+        None => (None, None),
+    };
+    let consumed = match stdout_pipe {
+        Some(pipe) => consume(&mut BufReader::new(pipe)),
+        None => consume(&mut std::io::empty()),
+    };
+    let mut child = child.lock().unwrap_or_else(|err| err.into_inner());
+    if consumed.is_err() {
+        // nobody reads the rest of the output
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    drop(child);
+    drop(done);
+    if let Some((watchdog, timeout)) = watchdog {
+        let timed_out = watchdog
+            .join()
+            .map_err(|_| anyhow!("failed to watch {}", name))?;
+        if timed_out {
+            return Err(anyhow!(
+                "{} timed out after {} seconds",
+                name,
+                timeout.as_secs()
+            ));
+        }
+    }
+    if let Some(stdin) = stdin {
+        stdin
+            .join()
+            .map_err(|_| anyhow!("failed to write the input of {}", name))?
+            .with_context(|| format!("failed to write the input of {}", name))?;
+    }
+    let consumed = consumed.with_context(|| format!("failed to read the output of {}", name))?;
+    let stderr = stderr
+        .join()
+        .map_err(|_| anyhow!("failed to read the output of {}", name))?
+        .with_context(|| format!("failed to read the output of {}", name))?;
+    Ok((status, stderr, consumed))
+}
+
+#[derive(Builder, Debug, PartialEq, Clone)]
+pub struct GitBlameOption {
+    #[builder(setter(into))]
+    file: String,
+    #[builder(setter(into, strip_option))]
+    repo_path: Option<String>,
+    #[builder(default = "false")]
+    reverse: bool,
+    #[builder(default = "false")]
+    line_number: bool,
+    #[builder(default = "false")]
+    line_porcelain: bool,
+    // passed as one -L each
+    #[builder(default)]
+    line_ranges: Vec<(i32, i32)>,
+    #[builder(default = "false")]
+    detect_moves: bool,
+    #[builder(default = "false")]
+    detect_copies: bool,
+    #[builder(default = "false")]
+    ignore_whitespace: bool,
+    #[builder(default = "false")]
+    first_parent: bool,
+    // only commits on a path between the ends of a range revision
+    #[builder(default = "false")]
+    ancestry_path: bool,
+    #[builder(setter(into))]
+    revision: String,
+}
+
+impl GitBlameOption {
+    // the arguments following the git binary
+    pub fn build_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(repo_path) = &self.repo_path {
+            args.extend([String::from("-C"), repo_path.clone()]);
+        }
+        args.push(String::from("blame"));
+        if self.reverse {
+            args.push(String::from("--reverse"));
+        }
+        // the plain output parser needs the line number in the original
+        // revision, so -n is implied unless porcelain output is requested
+        if self.line_number || !self.line_porcelain {
+            args.push(String::from("-n"));
+        }
+        if self.line_porcelain {
+            args.push(String::from("--line-porcelain"));
+        }
+        for (start, end) in &self.line_ranges {
+            args.extend([String::from("-L"), format!("{},{}", start, end)]);
+        }
+        if self.detect_moves {
+            args.push(String::from("-M"));
+        }
+        if self.detect_copies {
+            args.push(String::from("-C"));
+        }
+        if self.ignore_whitespace {
+            args.push(String::from("-w"));
+        }
+        if self.first_parent {
+            args.push(String::from("--first-parent"));
+        }
+        if self.ancestry_path {
+            args.push(String::from("--ancestry-path"));
+        }
+        args.extend([self.revision.clone(), self.file.clone()]);
+        args
+    }
+
+    pub fn build_command(&self) -> Command {
+        let mut command = git_command();
+        command.args(self.build_args());
+        command
+    }
+}
+pub fn git_current_revision(
+    repo_path: &str,
+    revision_format: RevisionFormat,
+    abbrev: Option<usize>,
+) -> Result<String> {
+    git_current_revision_with(&SystemGit, repo_path, revision_format, abbrev)
+}
+
+pub fn git_current_revision_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision_format: RevisionFormat,
+    abbrev: Option<usize>,
+) -> Result<String> {
+    git_rev_parse_with(runner, repo_path, "HEAD", revision_format, abbrev)
+        .with_context(|| format!("Failed to get current revision of {}", repo_path))
+}
+
+// git symbolic-ref -q HEAD fails with 1 without a message when HEAD is detached
+pub fn git_is_detached_head(repo_path: &str) -> Result<bool> {
+    git_is_detached_head_with(&SystemGit, repo_path)
+}
+
+pub fn git_is_detached_head_with(runner: &dyn GitRunner, repo_path: &str) -> Result<bool> {
+    let args = [
+        String::from("-C"),
+        repo_path.to_string(),
+        String::from("symbolic-ref"),
+        String::from("-q"),
+        String::from("HEAD"),
+    ];
+    let output = runner.run(&args, "git symbolic-ref")?;
+    match output.exit_code {
+        Some(0) => Ok(false),
+        Some(1) => Ok(true),
+        _ => Err(anyhow!(
+            "Failed to read HEAD of {}: {}",
+            repo_path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+// abbreviated commit of a revision such as a tag, which may name a tag object
+pub fn git_commit_revision(
+    repo_path: &str,
+    revision: &str,
+    revision_format: RevisionFormat,
+    abbrev: Option<usize>,
+) -> Result<String> {
+    git_rev_parse_format(
+        repo_path,
+        &format!("{}^{{commit}}", revision),
+        revision_format,
+        abbrev,
+    )
+}
+
+// how revisions stamped on tags are written
+#[derive(EnumString, Debug, PartialEq, Clone, Copy)]
+#[strum(serialize_all = "lowercase")]
+pub enum RevisionFormat {
+    // abbreviated to --abbrev digits or as short as git keeps unique
+    Short,
+    // full object names
+    Long,
+}
+
+fn git_rev_parse_format(
+    repo_path: &str,
+    revision: &str,
+    revision_format: RevisionFormat,
+    abbrev: Option<usize>,
+) -> Result<String> {
+    git_rev_parse_with(&SystemGit, repo_path, revision, revision_format, abbrev)
+}
+
+fn git_rev_parse_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision: &str,
+    revision_format: RevisionFormat,
+    abbrev: Option<usize>,
+) -> Result<String> {
+    let mut args = vec![
+        String::from("-C"),
+        repo_path.to_string(),
+        String::from("rev-parse"),
+    ];
+    match (revision_format, abbrev) {
+        (RevisionFormat::Short, Some(abbrev)) => args.push(format!("--short={}", abbrev)),
+        (RevisionFormat::Short, None) => args.push(String::from("--short")),
+        (RevisionFormat::Long, _) => {}
+    }
+    args.push(revision.to_string());
+    let output = runner.run(&args, "git rev-parse")?;
+    if !output.success() {
+        return Err(GitMemoError::RevisionNotFound {
+            revision: revision.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        }
+        .into());
+    }
+    let stdout_output =
+        String::from_utf8(output.stdout).context("git rev-parse output is not valid UTF-8")?;
+    Ok(stdout_output.trim().to_string())
+}
+
+pub fn git_rev_parse(repo_path: &str, revision: &str) -> Result<String> {
+    git_rev_parse_long_with(&SystemGit, repo_path, revision)
+}
+
+pub fn git_rev_parse_long_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision: &str,
+) -> Result<String> {
+    git_rev_parse_with(runner, repo_path, revision, RevisionFormat::Long, None)
+}
+
+// lines of the file at the revision
+pub fn git_show_file(repo_path: &str, revision: &str, path: &str) -> Result<Vec<String>> {
+    git_show_file_with(&SystemGit, repo_path, revision, path)
+}
+
+pub fn git_show_file_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision: &str,
+    path: &str,
+) -> Result<Vec<String>> {
+    let args = [
+        String::from("-C"),
+        repo_path.to_string(),
+        String::from("show"),
+        format!("{}:{}", revision, path.trim_start_matches("./")),
+    ];
+    let output = runner.run(&args, "git show")?;
+    if !output.success() {
+        return Err(anyhow!(
+            "Failed to show {} at {}: {}",
+            path,
+            revision,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitRename {
+    pub revision: String,
+    pub from: String,
+    pub to: String,
+}
+
+// renames of path after since_revision up to until_revision, newest first
+pub fn git_follow_renames(
+    repo_path: &str,
+    path: &str,
+    since_revision: &str,
+    until_revision: &str,
+) -> Result<Vec<GitRename>> {
+    git_follow_renames_with(&SystemGit, repo_path, path, since_revision, until_revision)
+}
+
+pub fn git_follow_renames_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    path: &str,
+    since_revision: &str,
+    until_revision: &str,
+) -> Result<Vec<GitRename>> {
+    let args = [
+        String::from("-C"),
+        repo_path.to_string(),
+        String::from("log"),
+        String::from("--follow"),
+        String::from("--name-status"),
+        String::from("--format=commit %H"),
+        format!("{}..{}", since_revision, until_revision),
+        String::from("--"),
+        path.trim_start_matches("./").to_string(),
+    ];
+    let output = runner.run(&args, "git log")?;
+    if !output.success() {
+        return Err(anyhow!(
+            "Failed to follow {} from {} to {}: {}",
+            path,
+            since_revision,
+            until_revision,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let stdout_output = String::from_utf8_lossy(&output.stdout);
+    let mut renames = Vec::new();
+    let mut revision = "";
+    // the name is followed back from until_revision
+    let mut current = path.trim_start_matches("./");
+    for line in stdout_output.lines() {
+        if let Some(commit) = line.strip_prefix("commit ") {
+            revision = commit;
+            continue;
+        }
+        // "R<score>\t<old path>\t<new path>"
+        let fields: Vec<&str> = line.split('\t').collect();
+        if let [status, from, to] = fields.as_slice() {
+            if status.starts_with('R') && *to == current {
+                renames.push(GitRename {
+                    revision: revision.to_string(),
+                    from: from.to_string(),
+                    to: to.to_string(),
+                });
+                current = from;
+            }
+        }
+    }
+    Ok(renames)
+}
+
+// lines at least this similar are taken for one another by --fuzz
+const FUZZY_SIMILARITY: f64 = 0.6;
+
+// Dice coefficient of the character bigrams of the trimmed lines, from 0 to 1
+fn line_similarity(a: &str, b: &str) -> f64 {
+    let bigrams = |line: &str| -> Vec<(char, char)> {
+        let chars: Vec<char> = line.trim().chars().collect();
+        chars.windows(2).map(|pair| (pair[0], pair[1])).collect()
+    };
+    let (a_bigrams, mut b_bigrams) = (bigrams(a), bigrams(b));
+    if a_bigrams.is_empty() || b_bigrams.is_empty() {
+        return if a.trim() == b.trim() { 1.0 } else { 0.0 };
+    }
+    let total = a_bigrams.len() + b_bigrams.len();
+    let mut shared = 0;
+    for bigram in &a_bigrams {
+        if let Some(index) = b_bigrams.iter().position(|other| other == bigram) {
+            b_bigrams.swap_remove(index);
+            shared += 1;
+        }
+    }
+    (2 * shared) as f64 / total as f64
+}
+
+// a line defining a function or a type, e.g. `pub fn name`, `def name` or `class name`
+fn symbol_definition(line: &str) -> Option<String> {
+    static SYMBOL_DEFINITION: OnceLock<Regex> = OnceLock::new();
+    let re = SYMBOL_DEFINITION.get_or_init(|| {
+        Regex::new(
+            r"^\s*(?:(?:pub(?:\([^)]*\))?|export|async|static|unsafe|const|default)\s+)*(?:fn|def|func|function|class|struct|enum|trait|impl|mod|interface)\s+([A-Za-z_][A-Za-z0-9_]*)",
+        )
+        .expect("valid symbol regex")
+    });
+    re.captures(line).map(|captures| captures[1].to_string())
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+// the nearest definition above the line which is indented no deeper than it
+pub fn enclosing_symbol(lines: &[String], line: i32) -> Option<SymbolAnchor> {
+    let target = lines.get((line - 1) as usize)?;
+    (0..line as usize)
+        .rev()
+        .filter(|index| indentation(&lines[*index]) <= indentation(target))
+        .find_map(|index| {
+            symbol_definition(&lines[index]).map(|name| SymbolAnchor {
+                name,
+                offset: line - (index as i32 + 1),
+            })
+        })
+}
+
+// line of the definition of the symbol, None when there is none or several
+pub fn find_symbol(lines: &[String], name: &str) -> Option<i32> {
+    let mut definitions = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| symbol_definition(line).as_deref() == Some(name));
+    match (definitions.next(), definitions.next()) {
+        (Some((index, _)), None) => Some(index as i32 + 1),
+        _ => None,
+    }
+}
+
+// 64-bit FNV-1a, which stays the same across builds unlike DefaultHasher
+pub fn line_content_hash(line: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in line.trim().bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+pub fn git_path_exists(repo_path: &str, revision: &str, path: &str) -> Result<bool> {
+    git_path_exists_with(&SystemGit, repo_path, revision, path)
+}
+
+pub fn git_path_exists_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision: &str,
+    path: &str,
+) -> Result<bool> {
+    let args = [
+        String::from("-C"),
+        repo_path.to_string(),
+        String::from("cat-file"),
+        String::from("-e"),
+        format!("{}:{}", revision, path),
+    ];
+    Ok(runner.run(&args, "git cat-file")?.success())
+}
+
+// commit the staged content without moving any ref, so that memos can be
+// tracked to what the next commit would contain
+pub fn git_commit_index(repo_path: &str) -> Result<String> {
+    git_commit_index_with(&SystemGit, repo_path)
+}
+
+pub fn git_commit_index_with(runner: &dyn GitRunner, repo_path: &str) -> Result<String> {
+    let tree = git_output_with(runner, repo_path, &["write-tree"])?;
+    let mut args = vec!["commit-tree", tree.as_str(), "-m", "git-memo check"];
+    let head_revision = git_rev_parse_long_with(runner, repo_path, "HEAD").ok();
+    if let Some(head_revision) = &head_revision {
+        args.extend(["-p", head_revision.as_str()]);
+    }
+    git_output_with(runner, repo_path, &args)
+}
+
+fn git_output_with(runner: &dyn GitRunner, repo_path: &str, args: &[&str]) -> Result<String> {
+    let mut git_args = vec![String::from("-C"), repo_path.to_string()];
+    git_args.extend(args.iter().map(|arg| arg.to_string()));
+    let output = runner.run(&git_args, &format!("git {}", args[0]))?;
+    if !output.success() {
+        return Err(anyhow!(
+            "Failed to run git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// git blame of a symlink annotates the path it points to, not lines of a file
+pub fn git_is_symlink(repo_path: &str, revision: &str, path: &str) -> Result<bool> {
+    git_is_symlink_with(&SystemGit, repo_path, revision, path)
+}
+
+pub fn git_is_symlink_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision: &str,
+    path: &str,
+) -> Result<bool> {
+    let entry = git_output_with(runner, repo_path, &["ls-tree", revision, "--", path])?;
+    Ok(entry.starts_with("120000 "))
+}
+
+// unix time of a git date such as "2 weeks ago" or "2022-03-01"
+pub fn git_parse_date(repo_path: &str, date: &str) -> Result<i64> {
+    git_parse_date_with(&SystemGit, repo_path, date)
+}
+
+pub fn git_parse_date_with(runner: &dyn GitRunner, repo_path: &str, date: &str) -> Result<i64> {
+    let args = [
+        String::from("-C"),
+        repo_path.to_string(),
+        String::from("rev-parse"),
+        format!("--since={}", date),
+    ];
+    let output = runner.run(&args, "git rev-parse")?;
+    // printed as --max-age=<unix time>
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .strip_prefix("--max-age=")
+        .and_then(|timestamp| timestamp.parse().ok())
+        .with_context(|| format!("invalid date: {}", date))
+}
+
+pub fn git_commit_time(repo_path: &str, revision: &str) -> Result<i64> {
+    git_commit_time_with(&SystemGit, repo_path, revision)
+}
+
+pub fn git_commit_time_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision: &str,
+) -> Result<i64> {
+    let args = [
+        String::from("-C"),
+        repo_path.to_string(),
+        String::from("log"),
+        String::from("-1"),
+        String::from("--format=%ct"),
+        revision.to_string(),
+    ];
+    let output = runner.run(&args, "git log")?;
+    if !output.success() {
+        return Err(anyhow!(
+            "Failed to get commit time of {}: {}",
+            revision,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid commit time of {}", revision))
+}
+
+// "YYYY-MM-DD HH:MM:SS +hhmm" like git log --format=%ci
+pub fn format_git_time(time: i64, tz: &str) -> String {
+    let sign = if tz.starts_with('-') { -1 } else { 1 };
+    let offset = tz.trim_start_matches(['+', '-']);
+    let offset = match (offset.get(0..2), offset.get(2..4)) {
+        (Some(hours), Some(minutes)) => {
+            hours.parse::<i64>().unwrap_or(0) * 3600 + minutes.parse::<i64>().unwrap_or(0) * 60
+        }
+        _ => 0,
+    };
+    let (year, month, day, seconds) = civil_date(time + sign * offset);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        tz
+    )
+}
+
+// "YYYY-MM-DDTHH:MM:SSZ"
+pub fn format_utc_time(time: i64) -> String {
+    let (year, month, day, seconds) = civil_date(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+// (year, month, day, seconds of the day) of seconds since 1970-01-01
+fn civil_date(time: i64) -> (i64, i64, i64, i64) {
+    let (days, seconds) = (time.div_euclid(86400), time.rem_euclid(86400));
+    // civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, seconds)
+}
+
+// merge overlapping and adjacent ranges into the fewest -L arguments
+pub fn coalesce_line_ranges(mut line_ranges: Vec<(i32, i32)>) -> Vec<(i32, i32)> {
+    line_ranges.sort_unstable();
+    let mut coalesced: Vec<(i32, i32)> = Vec::new();
+    for (start, end) in line_ranges {
+        match coalesced.last_mut() {
+            Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+            _ => coalesced.push((start, end)),
+        }
+    }
+    coalesced
+}
+
+// paths in memo files are relative to the repository root;
+// "./", "..", and absolute paths inside the root are folded away
+pub fn normalize_path(repo_root: &str, path: &str) -> String {
+    let path = Path::new(path);
+    let relative = match path.strip_prefix(repo_root) {
+        Ok(relative) => relative,
+        Err(_) if path.is_absolute() => return path.to_string_lossy().into(),
+        Err(_) => path,
+    };
+    let mut parts: Vec<String> = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into()),
+            Component::ParentDir => {
+                parts.pop();
+            }
+            _ => {}
+        }
+    }
+    parts.join("/")
+}
+
+pub fn git_show_toplevel(path: &str) -> Result<String> {
+    git_show_toplevel_with(&SystemGit, path)
+}
+
+pub fn git_show_toplevel_with(runner: &dyn GitRunner, path: &str) -> Result<String> {
+    let args = [
+        String::from("-C"),
+        path.to_string(),
+        String::from("rev-parse"),
+        String::from("--show-toplevel"),
+    ];
+    let output = runner.run(&args, "git rev-parse")?;
+    if !output.success() {
+        return Err(anyhow!(
+            "{} is not inside a git repository: {}",
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let stdout_output =
+        String::from_utf8(output.stdout).context("git rev-parse output is not valid UTF-8")?;
+    Ok(stdout_output.trim().to_string())
+}
+
+pub fn git_revision_exists(repo_path: &str, revision: &str) -> Result<bool> {
+    git_revision_exists_with(&SystemGit, repo_path, revision)
+}
+
+pub fn git_revision_exists_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision: &str,
+) -> Result<bool> {
+    let args = [
+        String::from("-C"),
+        repo_path.to_string(),
+        String::from("cat-file"),
+        String::from("-e"),
+        format!("{}^{{commit}}", revision),
+    ];
+    Ok(runner.run(&args, "git cat-file")?.success())
+}
+
+// resolve revisions once per run so that a full SHA and its abbreviations compare equal
+fn normalize_revision(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision: &str,
+    cache: &mut HashMap<String, String>,
+) -> Result<String> {
+    // git rev-parse echoes a full hash back without looking it up
+    if is_zero_revision(revision) {
+        return Err(anyhow!(
+            "{} names uncommitted lines, not a commit",
+            revision
+        ));
+    }
+    if let Some(full_revision) = cache.get(revision) {
+        return Ok(full_revision.clone());
+    }
+    let full_revision = git_rev_parse_long_with(runner, repo_path, revision)?;
+    cache.insert(revision.to_string(), full_revision.clone());
+    Ok(full_revision)
+}
+
+fn abbreviate_revision(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision: &str,
+    update_option: &UpdateOption,
+    cache: &mut HashMap<String, String>,
+) -> Result<String> {
+    if is_zero_revision(revision) {
+        return Ok(revision.to_string());
+    }
+    if let Some(short_revision) = cache.get(revision) {
+        return Ok(short_revision.clone());
+    }
+    let short_revision = git_rev_parse_with(
+        runner,
+        repo_path,
+        revision,
+        update_option.revision_format,
+        update_option.abbrev,
+    )?;
+    cache.insert(revision.to_string(), short_revision.clone());
+    Ok(short_revision)
+}
+
+pub fn git_merge_base_is_ancestor(
+    repo_path: &str,
+    revision1: &str,
+    revision2: &str,
+) -> Result<bool> {
+    git_merge_base_is_ancestor_with(&SystemGit, repo_path, revision1, revision2)
+}
+
+pub fn git_merge_base_is_ancestor_with(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    revision1: &str,
+    revision2: &str,
+) -> Result<bool> {
+    let args = [
+        "-C",
+        repo_path,
+        "merge-base",
+        "--is-ancestor",
+        revision1,
+        revision2,
+    ]
+    .map(String::from);
+    let output = runner.run(&args, "git merge-base")?;
+    let exit_code = output
+        .exit_code
+        .context("git merge-base was terminated by a signal")?;
+    match exit_code {
+        0 => Ok(true),
+        1 => Ok(false),
+        // 128 for a bad revision
+        _ => Err(anyhow!(
+            "Failed to run git merge-base --is-ancestor {} {}: exit_code={}, stderr={}",
+            revision1,
+            revision2,
+            exit_code,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+    }
+}
+
+pub fn git_blame_reverse(git_blame_option: &GitBlameOption) -> Result<Vec<GitBlameResult>> {
+    git_blame_reverse_with(&SystemGit, git_blame_option)
+}
+
+pub fn git_blame_reverse_with(
+    runner: &dyn GitRunner,
+    git_blame_option: &GitBlameOption,
+) -> Result<Vec<GitBlameResult>> {
+    // compiled once for all lines of the output
+    let re = Regex::new(BLAME_LINE_PATTERN)?;
+    // lines are parsed as they are read, so only the results are kept
+    let mut results = Vec::new();
+    let (exit_code, stderr) =
+        runner.stream(&git_blame_option.build_args(), "git blame", &mut |reader| {
+            let mut read_error = None;
+            // source lines are echoed in the output and may be in any encoding
+            let lines = reader.split(b'\n').map_while(|line| match line {
+                Ok(line) => Some(String::from_utf8_lossy(&line).into_owned()),
+                Err(err) => {
+                    read_error = Some(err);
+                    None
+                }
+            });
+            // a retried command parses from the start again
+            results = if git_blame_option.line_porcelain {
+                GitBlameResult::new_from_porcelain(lines)
+            } else {
+                let mut results = Vec::new();
+                for line in lines {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match GitBlameResult::new_from_line(&re, &line) {
+                        Ok(result) => results.push(result),
+                        Err(err) => warning!("warning: skip git blame line: {:#}", err),
+                    }
+                }
+                results
+            };
+            match read_error {
+                Some(err) => Err(err.into()),
+                None => Ok(()),
+            }
+        })?;
+
+    let exit_code = exit_code.context("git blame was terminated by a signal")?;
+    if exit_code != 0 {
+        let stderr_output = String::from_utf8_lossy(&stderr);
+        // -L beyond the end of the file
+        if !git_blame_option.line_ranges.is_empty() && stderr_output.contains(" has only ") {
+            return Ok(Vec::new());
+        }
+        return Err(GitMemoError::BlameFailed {
+            exit_code,
+            stderr: stderr_output.to_string(),
+        }
+        .into());
+    }
+    Ok(results)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct GitBlameResult {
+    pub revision: String,
+    pub orig_line_number: i32,
+    pub new_line_number: i32,
+    // path of the file in revision, only reported by porcelain output
+    pub file_name: Option<String>,
+    // of the attributed revision, only reported by porcelain output
+    pub author: Option<String>,
+    pub committed_at: Option<String>,
+    // the oldest commit of the blamed range, marked by '^' or a "boundary" header
+    pub boundary: bool,
+}
+// "<revision> <line in revision> (<author> <date> <line in blamed file>) <content>";
+// line numbers are padded, and the author may contain spaces and parentheses,
+// so the line number in the blamed file is the one right after the date
+const BLAME_LINE_PATTERN: &str = r"^(?P<revision>[^ ]+) +(?P<new_line>[0-9]+) \(.*? [0-9]{4}-[0-9]{2}-[0-9]{2} [0-9]{2}:[0-9]{2}:[0-9]{2} [+-][0-9]{4} +(?P<orig_line>[0-9]+)\)";
+
+impl GitBlameResult {
+    fn new_from_line(re: &Regex, line: &str) -> Result<GitBlameResult> {
+        // git on Windows may end lines with CRLF
+        let line = line.trim_end_matches('\r');
+        let captures = re
+            .captures(line)
+            .with_context(|| format!("failed to parse git blame line: {}", line))?;
+
+        let revision = captures
+            .name("revision")
+            .context("missing revision in git blame line")?
+            .as_str();
+        let (revision, boundary) = match revision.strip_prefix('^') {
+            Some(revision) => (revision, true),
+            None => (revision, false),
+        };
+        let new_line_number = captures
+            .name("new_line")
+            .context("missing line number in git blame line")?
+            .as_str()
+            .parse::<i32>()
+            .with_context(|| format!("invalid line number in git blame line: {}", line))?;
+        let orig_line_number = captures
+            .name("orig_line")
+            .context("missing original line number in git blame line")?
+            .as_str()
+            .parse::<i32>()
+            .with_context(|| format!("invalid line number in git blame line: {}", line))?;
+        Ok(GitBlameResult {
+            new_line_number,
+            orig_line_number,
+            revision: revision.to_string(),
+            file_name: None,
+            author: None,
+            committed_at: None,
+            boundary,
+        })
+    }
+
+    // each entry of --line-porcelain output starts with
+    // "<revision> <line in revision> <line in blamed file> [<lines in group>]"
+    // followed by header lines and ends with the content line prefixed by a tab
+    fn new_from_porcelain(lines: impl Iterator<Item = String>) -> Vec<GitBlameResult> {
+        let mut results: Vec<GitBlameResult> = Vec::new();
+        let mut in_header = false;
+        let mut header_parsed = false;
+        let mut committer_time: Option<i64> = None;
+        for line in lines {
+            if line.starts_with('\t') {
+                in_header = false;
+                continue;
+            }
+            // header fields must not keep the CR of CRLF line endings
+            let line = line.trim_end_matches('\r');
+            if in_header {
+                let result = match (header_parsed, results.last_mut()) {
+                    (true, Some(result)) => result,
+                    _ => continue,
+                };
+                if let Some(file_name) = line.strip_prefix("filename ") {
+                    result.file_name = Some(file_name.to_string());
+                } else if line == "boundary" {
+                    result.boundary = true;
+                } else if let Some(author) = line.strip_prefix("author ") {
+                    result.author = Some(author.to_string());
+                } else if let Some(time) = line.strip_prefix("committer-time ") {
+                    committer_time = time.parse().ok();
+                } else if let Some(tz) = line.strip_prefix("committer-tz ") {
+                    result.committed_at =
+                        committer_time.take().map(|time| format_git_time(time, tz));
+                }
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            in_header = true;
+            match GitBlameResult::new_from_porcelain_header(line) {
+                Ok(result) => {
+                    results.push(result);
+                    header_parsed = true;
+                }
+                Err(err) => {
+                    warning!("warning: skip git blame entry: {:#}", err);
+                    header_parsed = false;
+                }
+            }
+        }
+        results
+    }
+
+    fn new_from_porcelain_header(line: &str) -> Result<GitBlameResult> {
+        let mut fields = line.split(' ');
+        let revision = fields
+            .next()
+            .with_context(|| format!("failed to parse git blame header: {}", line))?;
+        let new_line_number = fields
+            .next()
+            .with_context(|| format!("missing line number in git blame header: {}", line))?
+            .parse::<i32>()
+            .with_context(|| format!("invalid line number in git blame header: {}", line))?;
+        let orig_line_number = fields
+            .next()
+            .with_context(|| format!("missing line number in git blame header: {}", line))?
+            .parse::<i32>()
+            .with_context(|| format!("invalid line number in git blame header: {}", line))?;
+        Ok(GitBlameResult {
+            revision: revision.to_string(),
+            orig_line_number,
+            new_line_number,
+            file_name: None,
+            author: None,
+            committed_at: None,
+            boundary: false,
+        })
+    }
+}
+
+#[derive(EnumString, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum MemoFormat {
+    Json,
+    Yaml,
+}
+
+#[derive(EnumString, Debug, PartialEq, Clone, Copy)]
+#[strum(serialize_all = "kebab-case")]
+pub enum MemoBackend {
+    Json,
+    GitNotes,
+}
+
+impl MemoFormat {
+    // fallback when --format is not given
+    pub fn from_path(path: &str) -> MemoFormat {
+        let path = path.strip_suffix(".gz").unwrap_or(path);
+        if path.ends_with(".yaml") || path.ends_with(".yml") {
+            MemoFormat::Yaml
+        } else {
+            MemoFormat::Json
+        }
+    }
+}
+
+pub fn load_root_data(input: &str, format: Option<MemoFormat>) -> Result<RootData> {
+    let reader: Box<dyn Read> = if input == "-" {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        Box::new(BufReader::new(
+            File::open(input)
+                .map_err(GitMemoError::Io)
+                .with_context(|| format!("failed to open {}", input))?,
+        ))
+    };
+    let reader: Box<dyn Read> = if input.ends_with(".gz") {
+        Box::new(BufReader::new(GzDecoder::new(reader)))
+    } else {
+        reader
+    };
+    if format.unwrap_or_else(|| MemoFormat::from_path(input)) == MemoFormat::Yaml {
+        // serde_yaml errors already carry the field path and location
+        let data: RootData = serde_yaml::from_reader(reader).map_err(|err| {
+            GitMemoError::ParseError(format!("invalid memo file {}: {}", input, err))
+        })?;
+        return migrate_root_data(data)
+            .and_then(|data| validate_root_data(&data).map(|_| data))
+            .with_context(|| format!("invalid memo file {}", input));
+    }
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let data: RootData = serde_path_to_error::deserialize(&mut deserializer)
+        .map_err(|err| describe_parse_error(input, err))?;
+    deserializer
+        .end()
+        .map_err(|err| GitMemoError::ParseError(format!("invalid memo file {}: {}", input, err)))?;
+    migrate_root_data(data)
+        .and_then(|data| validate_root_data(&data).map(|_| data))
+        .with_context(|| format!("invalid memo file {}", input))
+}
+
+fn describe_parse_error(
+    input: &str,
+    err: serde_path_to_error::Error<serde_json::Error>,
+) -> anyhow::Error {
+    let path = err.path().to_string();
+    let err = err.into_inner();
+    if err.is_io() {
+        let message = format!("failed to read memo file {}", input);
+        return anyhow::Error::new(GitMemoError::Io(err.into())).context(message);
+    }
+    let location = format!(" at line {} column {}", err.line(), err.column());
+    let message = err.to_string();
+    let mut message = message
+        .strip_suffix(&location)
+        .unwrap_or(&message)
+        .to_string();
+    if path.ends_with(".status") {
+        message = message.replacen("unknown variant", "invalid TagStatus", 1);
+    }
+    let message = if path == "." {
+        format!("invalid memo file {}{}: {}", input, location, message)
+    } else {
+        format!(
+            "invalid memo file {}{} (field {}): {}",
+            input, location, path, message
+        )
+    };
+    GitMemoError::ParseError(message).into()
+}
+
+pub fn save_root_data(output: &str, data: &RootData, format: Option<MemoFormat>) -> Result<()> {
+    let serialized = match format.unwrap_or_else(|| MemoFormat::from_path(output)) {
+        MemoFormat::Json => serde_json::to_string_pretty(data)?,
+        MemoFormat::Yaml => serde_yaml::to_string(data)?,
+    };
+    if output == "-" {
+        write_memo_bytes(std::io::stdout(), false, serialized.as_bytes())?;
+        return Ok(());
+    }
+    // the old file is only replaced by a complete new one, so a killed run leaves it intact
+    let temp = format!("{}.tmp", output);
+    let written = File::create(&temp)
+        .map_err(GitMemoError::Io)
+        .with_context(|| format!("failed to create {}", temp))
+        .and_then(|file| write_memo_bytes(file, output.ends_with(".gz"), serialized.as_bytes()))
+        .and_then(|file| Ok(file.sync_all()?))
+        .and_then(|_| {
+            std::fs::rename(&temp, output)
+                .map_err(GitMemoError::Io)
+                .with_context(|| format!("failed to rename {} to {}", temp, output))
+        });
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    written
+}
+
+fn write_memo_bytes<W: Write>(mut writer: W, compress: bool, bytes: &[u8]) -> Result<W> {
+    if compress {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        encoder.write_all(bytes)?;
+        // dropping the encoder would swallow errors of the trailer
+        return Ok(encoder.finish()?);
+    }
+    writer.write_all(bytes)?;
+    Ok(writer)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn add_memo(
+    target_repo: &str,
+    data: &mut RootData,
+    file: &str,
+    line: i32,
+    end_line: Option<i32>,
+    text: &str,
+    revision: &str,
+    meta: BTreeMap<String, String>,
+    symbol: bool,
+) -> Result<()> {
+    if line == FILE_LEVEL_LINE && (end_line.is_some() || symbol) {
+        return Err(anyhow!(
+            "--end-line and --symbol cannot be given for a memo on the whole file"
+        ));
+    }
+    let file = normalize_path(target_repo, file);
+    let file = file.as_str();
+    // uncommitted lines are only in the worktree
+    let uncommitted = is_zero_revision(revision);
+    let worktree_path = Path::new(target_repo).join(file);
+    let is_symlink = if uncommitted {
+        std::fs::symlink_metadata(&worktree_path).is_ok_and(|metadata| metadata.is_symlink())
+    } else {
+        git_is_symlink(target_repo, revision, file)?
+    };
+    if is_symlink {
+        return Err(anyhow!(
+            "{} is a symlink, which is not supported; add the memo to the file it points to",
+            file
+        ));
+    }
+    // files which are not committed yet have nothing to hash
+    let lines = if uncommitted {
+        std::fs::read_to_string(&worktree_path)
+            .ok()
+            .map(|contents| contents.lines().map(String::from).collect::<Vec<_>>())
+    } else {
+        git_show_file(target_repo, revision, file).ok()
+    }
+    .filter(|_| line != FILE_LEVEL_LINE);
+    let content_hash = lines.as_ref().and_then(|lines| {
+        lines
+            .get((line - 1) as usize)
+            .map(|line| line_content_hash(line))
+    });
+    let symbol = if symbol {
+        let anchor = lines
+            .as_ref()
+            .and_then(|lines| enclosing_symbol(lines, line))
+            .with_context(|| {
+                format!(
+                    "no function or type is defined around {}:{} at {}",
+                    file, line, revision
+                )
+            })?;
+        Some(anchor)
+    } else {
+        None
+    };
+    let comment = Comment {
+        text: String::from(text),
+        tags: vec![CommentTag {
+            revision: revision.to_string(),
+            line,
+            end_line,
+            status: if uncommitted {
+                TagStatus::Uncommitted
+            } else {
+                TagStatus::Normal
+            },
+            content_hash,
+            author: None,
+            committed_at: None,
+            tracked_at: None,
+            symbol,
+        }],
+        meta,
+    };
+    match data
+        .files
+        .iter_mut()
+        .find(|file_data| normalize_path(target_repo, &file_data.path) == file)
+    {
+        Some(file_data) => file_data.comments.push(comment),
+        None => data.files.push(FileData {
+            path: String::from(file),
+            repo: None,
+            comments: vec![comment],
+        }),
+    }
+    Ok(())
+}
+
+// line number of the line of the file at the revision which matches the pattern
+pub fn find_line(
+    target_repo: &str,
+    revision: &str,
+    file: &str,
+    pattern: &str,
+    nth: Option<usize>,
+) -> Result<i32> {
+    let file = normalize_path(target_repo, file);
+    let re = Regex::new(pattern).with_context(|| format!("invalid --match {}", pattern))?;
+    let matches: Vec<(usize, String)> = git_show_file(target_repo, revision, &file)?
+        .into_iter()
+        .enumerate()
+        .filter(|(_, line)| re.is_match(line))
+        .collect();
+    let line_index = match (matches.len(), nth) {
+        (0, _) => return Err(anyhow!("no line of {} matches {}", file, pattern)),
+        (_, Some(nth)) => {
+            matches
+                .get(nth)
+                .with_context(|| {
+                    format!(
+                        "--nth {} is out of range: {} lines of {} match {}",
+                        nth,
+                        matches.len(),
+                        file,
+                        pattern
+                    )
+                })?
+                .0
+        }
+        (1, None) => matches[0].0,
+        (_, None) => {
+            let candidates: Vec<String> = matches
+                .iter()
+                .enumerate()
+                .map(|(nth, (line_index, line))| format!("  {}: {}: {}", nth, line_index + 1, line))
+                .collect();
+            return Err(anyhow!(
+                "{} lines of {} match {}, choose one with --nth:\n{}",
+                matches.len(),
+                file,
+                pattern,
+                candidates.join("\n")
+            ));
+        }
+    };
+    Ok(line_index as i32 + 1)
+}
+
+// new line ranges of the hunks of `git diff -U0`-like output, without pure deletions
+fn parse_hunk_ranges(diff: &str) -> Vec<(i32, i32)> {
+    // "@@ -<old start>[,<count>] +<new start>[,<count>] @@"
+    let re = Regex::new(r"^@@ -[0-9,]+ \+(?P<start>[0-9]+)(,(?P<count>[0-9]+))? @@").unwrap();
+    diff.lines()
+        .filter_map(|line| re.captures(line))
+        .filter_map(|captures| {
+            let start: i32 = captures["start"].parse().ok()?;
+            let count: i32 = match captures.name("count") {
+                Some(count) => count.as_str().parse().ok()?,
+                None => 1,
+            };
+            (count > 0).then(|| (start, start + count - 1))
+        })
+        .collect()
+}
+
+// line range of the last hunk of the uncommitted changes to the file, in the worktree and
+// else in the index, or otherwise of the change the revision made to it, with the revision
+// its lines are at; uncommitted lines are at no commit, so they are at the all-zero revision
+pub fn find_hunk(target_repo: &str, revision: &str, file: &str) -> Result<(String, i32, i32)> {
+    find_hunk_with(&SystemGit, target_repo, revision, file)
+}
+
+pub fn find_hunk_with(
+    runner: &dyn GitRunner,
+    target_repo: &str,
+    revision: &str,
+    file: &str,
+) -> Result<(String, i32, i32)> {
+    let file = normalize_path(target_repo, file);
+    let diff = |args: &[&str]| -> Result<String> {
+        let mut git_args = vec![String::from("-C"), target_repo.to_string()];
+        git_args.extend(args.iter().map(|arg| arg.to_string()));
+        git_args.extend([
+            String::from("--"),
+            file.trim_start_matches("./").to_string(),
+        ]);
+        let output = runner.run(&git_args, "git diff")?;
+        if !output.success() {
+            return Err(anyhow!(
+                "Failed to diff {} at {}: {}",
+                file,
+                revision,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    // uncommitted changes are made on top of HEAD, not of another revision
+    if git_rev_parse_long_with(runner, target_repo, revision)?
+        == git_rev_parse_long_with(runner, target_repo, "HEAD")?
+    {
+        let worktree = diff(&["diff", "-U0"])?;
+        // staged lines are where the worktree has them only if it changed nothing else
+        let uncommitted = if worktree.is_empty() {
+            diff(&["diff", "--cached", "-U0"])?
+        } else {
+            worktree
+        };
+        if let Some((start, end)) = parse_hunk_ranges(&uncommitted).last() {
+            return Ok((ZERO_REVISION.to_string(), *start, *end));
+        }
+    }
+    match parse_hunk_ranges(&diff(&["show", "--format=", "-U0", revision])?).last() {
+        Some((start, end)) => Ok((revision.to_string(), *start, *end)),
+        None => Err(anyhow!(
+            "{} has no changes in {} which add lines",
+            file,
+            revision
+        )),
+    }
+}
+
+// conditions on the memos to show, all of which must hold
+#[derive(Debug, Clone, Default)]
+pub struct MemoFilter {
+    // (key, value) pairs the meta of a comment must have
+    pub meta: Vec<(String, String)>,
+    // status of the latest tag
+    pub status: Option<TagStatus>,
+    // full hash the (possibly abbreviated) revision of the latest tag must be a prefix of
+    pub revision: Option<String>,
+    // matched against the path from the repository root
+    pub path_glob: Option<GlobMatcher>,
+}
+
+impl MemoFilter {
+    pub fn matches(&self, comment: &Comment) -> bool {
+        let tag = comment.tags.last();
+        self.meta
+            .iter()
+            .all(|(key, value)| comment.meta.get(key) == Some(value))
+            && self
+                .status
+                .as_ref()
+                .is_none_or(|status| tag.is_some_and(|tag| &tag.status == status))
+            && self.revision.as_ref().is_none_or(|revision| {
+                tag.is_some_and(|tag| {
+                    !tag.revision.is_empty() && revision.starts_with(&tag.revision)
+                })
+            })
+    }
+
+    pub fn matches_path(&self, target_repo: &str, file_data: &FileData) -> bool {
+        self.path_glob.as_ref().is_none_or(|glob| {
+            glob.is_match(normalize_path(
+                &file_data.repo_path(target_repo),
+                &file_data.path,
+            ))
+        })
+    }
+}
+
+// the memos matching the filter, leaving out files without any
+pub fn filter_memos(target_repo: &str, data: &RootData, filter: &MemoFilter) -> RootData {
+    RootData {
+        version: data.version,
+        files: data
+            .files
+            .iter()
+            .filter(|file_data| filter.matches_path(target_repo, file_data))
+            .map(|file_data| FileData {
+                comments: file_data
+                    .comments
+                    .iter()
+                    .filter(|comment| filter.matches(comment))
+                    .cloned()
+                    .collect(),
+                ..file_data.clone()
+            })
+            .filter(|file_data| !file_data.comments.is_empty())
+            .collect(),
+    }
+}
+
+// the memo is looked up by the line it is tracked to at HEAD
+pub fn remove_memo(
+    target_repo: &str,
+    data: &mut RootData,
+    file: &str,
+    line: i32,
+    text: Option<&str>,
+    index: Option<usize>,
+) -> Result<Comment> {
+    let file = normalize_path(target_repo, file);
+    let mut resolver = TagResolver::default();
+    let file_index = data
+        .files
+        .iter()
+        .position(|file_data| normalize_path(target_repo, &file_data.path) == file)
+        .with_context(|| format!("no memo in {}", file))?;
+    let file_data = &mut data.files[file_index];
+    let mut matches = Vec::new();
+    for (comment_index, comment) in file_data.comments.iter().enumerate() {
+        if text.is_some_and(|text| text != comment.text) {
+            continue;
+        }
+        if let Some(tag) = resolver.resolve(&file_data.repo_path(target_repo), comment)? {
+            if tag.line == line {
+                matches.push(comment_index);
+            }
+        }
+    }
+    let comment_index = match (matches.len(), index) {
+        (0, _) => return Err(anyhow!("no memo at {}:{}", file, line)),
+        (_, Some(index)) => *matches.get(index).with_context(|| {
+            format!(
+                "--index {} is out of range: {} memos at {}:{}",
+                index,
+                matches.len(),
+                file,
+                line
+            )
+        })?,
+        (1, None) => matches[0],
+        (_, None) => {
+            let candidates: Vec<String> = matches
+                .iter()
+                .enumerate()
+                .map(|(index, comment_index)| {
+                    format!("  {}: {}", index, file_data.comments[*comment_index].text)
+                })
+                .collect();
+            return Err(anyhow!(
+                "{} memos at {}:{}, choose one with --index or --text:\n{}",
+                matches.len(),
+                file,
+                line,
+                candidates.join("\n")
+            ));
+        }
+    };
+    let comment = file_data.comments.remove(comment_index);
+    if file_data.comments.is_empty() {
+        data.files.remove(file_index);
+    }
+    Ok(comment)
+}
+
+// map a tag onto the working tree, which git blame annotates when no
+// final revision is given; None when a line has uncommitted changes
+// (those lines are attributed to the all-zero revision)
+pub fn locate_in_worktree(
+    target_repo: &str,
+    path: &str,
+    tag: &CommentTag,
+    blame_cache: &mut HashMap<String, Vec<GitBlameResult>>,
+) -> Result<Option<CommentTag>> {
+    let path = normalize_path(target_repo, path);
+    if !Path::new(target_repo).join(&path).exists() {
+        return Ok(None);
+    }
+    if tag.is_file_level() {
+        return Ok(Some(tag.clone()));
+    }
+    let key = format!("{}:{}", tag.revision, path);
+    if !blame_cache.contains_key(&key) {
+        let git_blame_option = GitBlameOptionBuilder::default()
+            .file(&path)
+            .repo_path(target_repo)
+            .line_porcelain(true)
+            // lines unchanged since the tag are attributed to it as the boundary
+            .revision(format!("^{}", tag.revision))
+            .build()?;
+        blame_cache.insert(key.clone(), git_blame_reverse(&git_blame_option)?);
+    }
+    let results = &blame_cache[&key];
+    // new_line_number is the line at the tag and orig_line_number the line in the working tree
+    let locate = |line: i32| {
+        results
+            .iter()
+            .find(|result| {
+                result.revision.starts_with(&tag.revision) && result.new_line_number == line
+            })
+            .map(|result| result.orig_line_number)
+    };
+    let line = match locate(tag.line) {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let end_line = match tag.end_line {
+        Some(end_line) => match locate(end_line) {
+            Some(end_line) => Some(end_line),
+            None => return Ok(None),
+        },
+        None => None,
+    };
+    Ok(Some(CommentTag {
+        line,
+        end_line,
+        ..tag.clone()
+    }))
+}
+
+// files by path and comments by the line of their latest tag;
+// the sorts are stable so memos on the same line keep their order
+pub fn sort_memos(data: &mut RootData) {
+    data.files.sort_by(|a, b| a.path.cmp(&b.path));
+    for file_data in &mut data.files {
+        file_data
+            .comments
+            .sort_by_key(|comment| comment.tags.last().map_or(i32::MAX, |tag| tag.line));
+    }
+}
+
+// keep only the latest tag of each status (Normal, Fuzzy, Missing, Unknown, Uncommitted) of each comment
+pub fn gc_memos(data: &mut RootData) {
+    for file_data in &mut data.files {
+        for comment in &mut file_data.comments {
+            let latest: Vec<usize> = [
+                TagStatus::Normal,
+                TagStatus::Fuzzy,
+                TagStatus::Missing,
+                TagStatus::Unknown,
+                TagStatus::Uncommitted,
+            ]
+            .iter()
+            .filter_map(|status| comment.tags.iter().rposition(|tag| tag.status == *status))
+            .collect();
+            let mut index = 0;
+            comment.tags.retain(|_| {
+                let keep = latest.contains(&index);
+                index += 1;
+                keep
+            });
+        }
+    }
+}
+
+// replace the tags of each comment with its Normal tag at HEAD and return
+// (path, comment) of the comments which have none, which are left as they are
+pub fn migrate_memos(target_repo: &str, data: &mut RootData) -> Result<Vec<(String, Comment)>> {
+    let mut head_revisions: HashMap<String, String> = HashMap::new();
+    let mut revisions: HashMap<String, String> = HashMap::new();
+    let mut unplaced = Vec::new();
+    for file_data in &mut data.files {
+        let repo = file_data.repo_path(target_repo);
+        if !head_revisions.contains_key(&repo) {
+            let head_revision = git_rev_parse(&repo, "HEAD")?;
+            head_revisions.insert(repo.clone(), head_revision);
+        }
+        let head_revision = &head_revisions[&repo];
+        for comment in &mut file_data.comments {
+            // memos on the whole file keep their only position
+            let at_head = comment.tags.iter().rposition(|tag| {
+                tag.status.is_anchored()
+                    && (tag.is_file_level()
+                        || normalize_revision(&SystemGit, &repo, &tag.revision, &mut revisions)
+                            .is_ok_and(|revision| revision == *head_revision))
+            });
+            match at_head {
+                Some(index) => comment.tags = vec![comment.tags.swap_remove(index)],
+                None => unplaced.push((file_data.path.clone(), comment.clone())),
+            }
+        }
+    }
+    Ok(unplaced)
+}
+
+pub fn resolve_tag<'a>(
+    target_repo: &str,
+    comment: &'a Comment,
+    head_revision: &str,
+    revisions: &mut HashMap<String, String>,
+) -> Result<Option<&'a CommentTag>> {
+    for tag in comment.tags.iter().rev() {
+        if normalize_revision(&SystemGit, target_repo, &tag.revision, revisions)
+            .ok()
+            .as_deref()
+            == Some(head_revision)
+        {
+            return Ok(Some(tag));
+        }
+    }
+    for tag in comment.tags.iter().rev() {
+        // tags whose revision is not in the repository cannot be placed
+        if normalize_revision(&SystemGit, target_repo, &tag.revision, revisions).is_err() {
+            continue;
+        }
+        if git_merge_base_is_ancestor(target_repo, &tag.revision, "HEAD")? {
+            return Ok(Some(tag));
+        }
+    }
+    Ok(None)
+}
+
+// resolve_tag against HEAD of the repository of each file
+#[derive(Debug, Default)]
+pub struct TagResolver {
+    head_revisions: HashMap<String, String>,
+    revisions: HashMap<String, HashMap<String, String>>,
+}
+
+impl TagResolver {
+    pub fn resolve<'a>(
+        &mut self,
+        repo_path: &str,
+        comment: &'a Comment,
+    ) -> Result<Option<&'a CommentTag>> {
+        if !self.head_revisions.contains_key(repo_path) {
+            let head_revision = git_rev_parse(repo_path, "HEAD")?;
+            self.head_revisions
+                .insert(repo_path.to_string(), head_revision);
+        }
+        resolve_tag(
+            repo_path,
+            comment,
+            &self.head_revisions[repo_path],
+            self.revisions.entry(repo_path.to_string()).or_default(),
+        )
+    }
+}
+
+#[derive(Builder, Debug, PartialEq, Clone)]
+pub struct UpdateOption {
+    // tracks from this revision instead of the revision of each tag
+    #[builder(default)]
+    from: Option<String>,
+    #[builder(setter(into), default = "String::from(\"HEAD\")")]
+    to: String,
+    #[builder(default)]
+    abbrev: Option<usize>,
+    #[builder(default = "RevisionFormat::Short")]
+    revision_format: RevisionFormat,
+    #[builder(default)]
+    jobs: Option<usize>,
+    #[builder(default = "false")]
+    detect_moves: bool,
+    #[builder(default = "false")]
+    detect_copies: bool,
+    #[builder(default = "false")]
+    ignore_whitespace: bool,
+    #[builder(default = "false")]
+    first_parent: bool,
+    #[builder(default = "false")]
+    ancestry_path: bool,
+    #[builder(default = "false")]
+    backward: bool,
+    // finds the path at the revision of a tag with git log --follow when the
+    // file does not exist there under its current path
+    #[builder(default = "false")]
+    follow: bool,
+    // lines around the expected position searched for a similar line when a tag is lost
+    #[builder(default)]
+    fuzz: Option<usize>,
+    // git date; tags committed before it are left as they are
+    #[builder(default)]
+    since: Option<String>,
+    #[builder(default = "false")]
+    record_author: bool,
+    // globs of paths from the repository root which are not tracked
+    #[builder(default)]
+    ignore: Vec<String>,
+    // globs of paths from the repository root which are tracked, all when empty
+    #[builder(default)]
+    include: Vec<String>,
+    #[builder(default = "false")]
+    verbose: bool,
+    // progress bar of the comments tracked so far on stderr
+    #[builder(default = "false")]
+    progress: bool,
+}
+
+pub fn update_memos(
+    target_repo: &str,
+    data: RootData,
+    update_option: &UpdateOption,
+) -> Result<RootData> {
+    update_memos_with(&SystemGit, target_repo, data, update_option)
+}
+
+// every git command of the tracking is run by the runner, so that it can be
+// driven by canned output without git
+pub fn update_memos_with(
+    runner: &dyn GitRunner,
+    target_repo: &str,
+    mut data: RootData,
+    update_option: &UpdateOption,
+) -> Result<RootData> {
+    let glob_set = |patterns: &[String]| -> Result<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(Glob::new(pattern).with_context(|| format!("invalid glob {}", pattern))?);
+        }
+        Ok(builder.build()?)
+    };
+    let ignore = glob_set(&update_option.ignore)?;
+    let include = glob_set(&update_option.include)?;
+    // ignored files and files out of the pathspec keep their tags as they are
+    let is_ignored = |file_data: &FileData| {
+        let path = normalize_path(&file_data.repo_path(target_repo), &file_data.path);
+        ignore.is_match(&path) || !(include.is_empty() || include.is_match(&path))
+    };
+    let ignored_files = data
+        .files
+        .iter()
+        .filter(|file_data| is_ignored(file_data))
+        .count();
+    // HEAD and the other revisions are resolved in the repository of each file
+    let mut ranges: HashMap<String, TrackingRange> = HashMap::new();
+    for file_data in data.files.iter().filter(|file_data| !is_ignored(file_data)) {
+        if let Entry::Vacant(entry) = ranges.entry(file_data.repo_path(target_repo)) {
+            let range = TrackingRange::resolve(runner, entry.key(), update_option)?;
+            entry.insert(range);
+        }
+    }
+    let progress = if update_option.progress {
+        let total = data
+            .files
+            .iter()
+            .filter(|file_data| !is_ignored(file_data))
+            .map(|file_data| file_data.comments.len());
+        ProgressBar::new(total.sum::<usize>() as u64)
+    } else {
+        ProgressBar::hidden()
+    };
+    // files are independent, so each of them is updated in place by its own task
+    let mut update_files = || -> Result<Vec<String>> {
+        data.files
+            .par_iter_mut()
+            .filter(|file_data| !is_ignored(file_data))
+            .map(|file_data| {
+                let repo = file_data.repo_path(target_repo);
+                update_file_memos(
+                    runner,
+                    &repo,
+                    file_data,
+                    &ranges[&repo],
+                    update_option,
+                    &progress,
+                )
+            })
+            .filter_map(Result::transpose)
+            .collect()
+    };
+    let deleted_paths = match update_option.jobs {
+        Some(jobs) => rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build()?
+            .install(update_files)?,
+        None => update_files()?,
+    };
+    progress.finish_and_clear();
+    if ignored_files > 0 {
+        warning!(
+            "{} files skipped by ignore rules or the pathspec",
+            ignored_files
+        );
+    }
+    if !deleted_paths.is_empty() {
+        warning!(
+            "warning: memos orphaned by deleted files: {}",
+            deleted_paths.join(", ")
+        );
+    }
+    Ok(data)
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct UpdateSummary {
+    pub advanced: usize,
+    pub missing: usize,
+    pub unchanged: usize,
+}
+
+impl UpdateSummary {
+    pub fn has_changes(&self) -> bool {
+        self.advanced > 0 || self.missing > 0
+    }
+}
+
+impl std::fmt::Display for UpdateSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} tags advanced, {} marked Missing, {} unchanged",
+            self.advanced, self.missing, self.unchanged
+        )
+    }
+}
+
+// (path, comment, tag) of memos which update_memos newly marked Missing
+pub fn lost_memos<'a>(
+    data: &RootData,
+    new_data: &'a RootData,
+) -> Vec<(&'a str, &'a Comment, &'a CommentTag)> {
+    let mut lost = Vec::new();
+    for (file_data, new_file_data) in data.files.iter().zip(&new_data.files) {
+        for (comment, new_comment) in file_data.comments.iter().zip(&new_file_data.comments) {
+            let new_tags = &new_comment.tags[comment.tags.len().min(new_comment.tags.len())..];
+            if let Some(tag) = new_tags.iter().find(|tag| tag.status == TagStatus::Missing) {
+                lost.push((new_file_data.path.as_str(), new_comment, tag));
+            }
+        }
+    }
+    lost
+}
+
+// update_memos only appends tags, so the new tags of each comment follow the old ones
+pub fn summarize_update(data: &RootData, new_data: &RootData) -> UpdateSummary {
+    let mut summary = UpdateSummary::default();
+    for (file_data, new_file_data) in data.files.iter().zip(&new_data.files) {
+        for (comment, new_comment) in file_data.comments.iter().zip(&new_file_data.comments) {
+            let new_tags = &new_comment.tags[comment.tags.len().min(new_comment.tags.len())..];
+            if new_tags.is_empty() {
+                summary.unchanged += 1;
+            }
+            for tag in new_tags {
+                match tag.status {
+                    TagStatus::Normal | TagStatus::Fuzzy | TagStatus::Uncommitted => {
+                        summary.advanced += 1
+                    }
+                    TagStatus::Missing => summary.missing += 1,
+                    TagStatus::Unknown => {}
+                }
+            }
+        }
+    }
+    summary
+}
+
+// the latest tag of each comment before and after a tracking run, in the
+// style of a unified diff: " " untouched, "-" old position, "+" new position
+pub fn diff_update(data: &RootData, new_data: &RootData) -> String {
+    let describe = |tag: &CommentTag, text: &str| {
+        let status = match &tag.status {
+            TagStatus::Normal => String::new(),
+            status => format!(" ({})", status),
+        };
+        format!("{}@{}{}: {}", tag.line_span(), tag.revision, status, text)
+    };
+    let mut diff = String::new();
+    for (file_data, new_file_data) in data.files.iter().zip(&new_data.files) {
+        diff.push_str(&format!(
+            "--- {}\n+++ {}\n",
+            file_data.path, new_file_data.path
+        ));
+        for (comment, new_comment) in file_data.comments.iter().zip(&new_file_data.comments) {
+            let (Some(tag), Some(new_tag)) = (comment.tags.last(), new_comment.tags.last()) else {
+                continue;
+            };
+            if new_comment.tags.len() == comment.tags.len() && tag.status == new_tag.status {
+                diff.push_str(&format!(" {}\n", describe(tag, &comment.text)));
+            } else {
+                diff.push_str(&format!("-{}\n", describe(tag, &comment.text)));
+                diff.push_str(&format!("+{}\n", describe(new_tag, &new_comment.text)));
+            }
+        }
+    }
+    diff
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Clone)]
+pub struct MemoStats {
+    pub files: usize,
+    pub comments: usize,
+    pub comments_per_file: f64,
+    pub normal_tags: usize,
+    pub missing_tags: usize,
+    pub unknown_tags: usize,
+    pub fuzzy_tags: usize,
+    pub uncommitted_tags: usize,
+    // the revision of the oldest commit among the latest Normal or Fuzzy tags of the comments
+    pub oldest_revision: Option<String>,
+}
+
+impl std::fmt::Display for MemoStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "files              {}", self.files)?;
+        writeln!(f, "comments           {}", self.comments)?;
+        writeln!(f, "comments per file  {:.2}", self.comments_per_file)?;
+        writeln!(f, "Normal tags        {}", self.normal_tags)?;
+        writeln!(f, "Missing tags       {}", self.missing_tags)?;
+        writeln!(f, "Unknown tags       {}", self.unknown_tags)?;
+        writeln!(f, "Fuzzy tags         {}", self.fuzzy_tags)?;
+        writeln!(f, "Uncommitted tags   {}", self.uncommitted_tags)?;
+        writeln!(
+            f,
+            "oldest revision    {}",
+            self.oldest_revision.as_deref().unwrap_or("-")
+        )
+    }
+}
+
+pub fn memo_stats(target_repo: &str, data: &RootData) -> Result<MemoStats> {
+    let mut stats = MemoStats {
+        files: data.files.len(),
+        ..MemoStats::default()
+    };
+    // (commit time, revision) of the oldest tracked revision
+    let mut oldest: Option<(i64, String)> = None;
+    let mut commit_times: HashMap<(String, String), Option<i64>> = HashMap::new();
+    for file_data in &data.files {
+        let repo = file_data.repo_path(target_repo);
+        stats.comments += file_data.comments.len();
+        for comment in &file_data.comments {
+            for tag in &comment.tags {
+                match tag.status {
+                    TagStatus::Normal => stats.normal_tags += 1,
+                    TagStatus::Missing => stats.missing_tags += 1,
+                    TagStatus::Unknown => stats.unknown_tags += 1,
+                    TagStatus::Fuzzy => stats.fuzzy_tags += 1,
+                    TagStatus::Uncommitted => stats.uncommitted_tags += 1,
+                }
+            }
+            let tag = match comment.tags.last() {
+                Some(tag) if tag.status.is_anchored() => tag,
+                _ => continue,
+            };
+            let key = (repo.clone(), tag.revision.clone());
+            if !commit_times.contains_key(&key) {
+                // revisions which are not in the repository have no time
+                let commit_time = git_commit_time(&repo, &tag.revision).ok();
+                commit_times.insert(key.clone(), commit_time);
+            }
+            if let Some(commit_time) = commit_times[&key] {
+                if oldest.as_ref().is_none_or(|(time, _)| commit_time < *time) {
+                    oldest = Some((commit_time, tag.revision.clone()));
+                }
+            }
+        }
+    }
+    if stats.files > 0 {
+        stats.comments_per_file = stats.comments as f64 / stats.files as f64;
+    }
+    stats.oldest_revision = oldest.map(|(_, revision)| revision);
+    Ok(stats)
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct DoctorReport {
+    pub normal: usize,
+    pub missing: usize,
+    pub unknown: usize,
+    pub fuzzy: usize,
+    pub uncommitted: usize,
+    // (path, revision) of tags at revisions which are not in the repository
+    pub nonexistent_revisions: Vec<(String, String)>,
+    // (path, revision) of tags at revisions which HEAD does not descend from
+    pub unrelated_revisions: Vec<(String, String)>,
+    // paths of memo files which are not present at HEAD
+    pub absent_files: Vec<String>,
+}
+
+impl DoctorReport {
+    pub fn has_problems(&self) -> bool {
+        !self.nonexistent_revisions.is_empty()
+            || !self.unrelated_revisions.is_empty()
+            || !self.absent_files.is_empty()
+    }
+}
+
+impl std::fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "tags: {} Normal, {} Missing, {} Unknown, {} Fuzzy, {} Uncommitted",
+            self.normal, self.missing, self.unknown, self.fuzzy, self.uncommitted
+        )?;
+        writeln!(
+            f,
+            "nonexistent revisions: {}",
+            self.nonexistent_revisions.len()
+        )?;
+        for (path, revision) in &self.nonexistent_revisions {
+            writeln!(f, "  {}: {}", path, revision)?;
+        }
+        writeln!(
+            f,
+            "revisions not in the history of HEAD: {}",
+            self.unrelated_revisions.len()
+        )?;
+        for (path, revision) in &self.unrelated_revisions {
+            writeln!(f, "  {}: {}", path, revision)?;
+        }
+        writeln!(f, "files not present at HEAD: {}", self.absent_files.len())?;
+        for path in &self.absent_files {
+            writeln!(f, "  {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+// read-only health check of every tag against the repository
+pub fn diagnose_memos(target_repo: &str, data: &RootData) -> Result<DoctorReport> {
+    let mut report = DoctorReport::default();
+    // (repo, revision) -> None if the revision does not exist, else whether HEAD descends from it
+    let mut checked: HashMap<(String, String), Option<bool>> = HashMap::new();
+    for file_data in &data.files {
+        let repo = file_data.repo_path(target_repo);
+        let path = normalize_path(&repo, &file_data.path);
+        if !git_path_exists(&repo, "HEAD", &path)? {
+            report.absent_files.push(file_data.path.clone());
+        }
+        let mut revisions: Vec<&str> = Vec::new();
+        for tag in file_data.comments.iter().flat_map(|comment| &comment.tags) {
+            match tag.status {
+                TagStatus::Normal => report.normal += 1,
+                TagStatus::Missing => report.missing += 1,
+                TagStatus::Unknown => report.unknown += 1,
+                TagStatus::Fuzzy => report.fuzzy += 1,
+                TagStatus::Uncommitted => report.uncommitted += 1,
+            }
+            // the all-zero revision is never in the repository
+            if is_zero_revision(&tag.revision) || revisions.contains(&tag.revision.as_str()) {
+                continue;
+            }
+            revisions.push(&tag.revision);
+            let key = (repo.clone(), tag.revision.clone());
+            if !checked.contains_key(&key) {
+                let state = if git_revision_exists(&repo, &tag.revision)? {
+                    Some(git_merge_base_is_ancestor(&repo, &tag.revision, "HEAD")?)
+                } else {
+                    None
+                };
+                checked.insert(key.clone(), state);
+            }
+            match checked[&key] {
+                None => report
+                    .nonexistent_revisions
+                    .push((file_data.path.clone(), tag.revision.clone())),
+                Some(false) => report
+                    .unrelated_revisions
+                    .push((file_data.path.clone(), tag.revision.clone())),
+                Some(true) => {}
+            }
+        }
+    }
+    Ok(report)
+}
+
+// revisions and dates of UpdateOption resolved once per run
+struct TrackingRange {
+    target_revision: String,
+    from_revision: Option<String>,
+    since: Option<i64>,
+    // stamped on every tag pushed by the run
+    tracked_at: String,
+}
+
+impl TrackingRange {
+    fn resolve(
+        runner: &dyn GitRunner,
+        target_repo: &str,
+        update_option: &UpdateOption,
+    ) -> Result<TrackingRange> {
+        Ok(TrackingRange {
+            target_revision: git_rev_parse_long_with(runner, target_repo, &update_option.to)?,
+            from_revision: match &update_option.from {
+                Some(from) => Some(git_rev_parse_long_with(runner, target_repo, from)?),
+                None => None,
+            },
+            since: match &update_option.since {
+                Some(date) => Some(git_parse_date_with(runner, target_repo, date)?),
+                None => None,
+            },
+            tracked_at: format_utc_time(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .context("system clock is before 1970")?
+                    .as_secs() as i64,
+            ),
+        })
+    }
+}
+
+fn is_no_such_path<T>(result: &Result<T>) -> bool {
+    matches!(
+        result.as_ref().map_err(|err| err.downcast_ref::<GitMemoError>()),
+        Err(Some(GitMemoError::BlameFailed { stderr, .. })) if stderr.contains("no such path")
+    )
+}
+
+// per-file state shared by the comments of a file
+struct FileTracker<'a> {
+    runner: &'a dyn GitRunner,
+    target_repo: &'a str,
+    // relative to the repository root
+    path: String,
+    target_revision: &'a str,
+    from_revision: Option<&'a str>,
+    since: Option<i64>,
+    tracked_at: &'a str,
+    update_option: &'a UpdateOption,
+    // warnings are printed above the bar
+    progress: &'a ProgressBar,
+    revisions: HashMap<String, String>,
+    short_revisions: HashMap<String, String>,
+    blame_cache: HashMap<String, Vec<GitBlameResult>>,
+    existing_revisions: HashMap<String, bool>,
+    target_contents: HashMap<String, Vec<String>>,
+    commit_times: HashMap<String, i64>,
+    // lines of the tags of each revision which are not blamed yet
+    pending_ranges: HashMap<String, Vec<(i32, i32)>>,
+    // paths blame reported for the tracked lines
+    new_paths: Vec<String>,
+    // path at each revision found by following renames, None when not renamed
+    followed_paths: HashMap<String, Option<String>>,
+    // lines of the file at the revision of tags, for --fuzz
+    source_contents: HashMap<String, Vec<String>>,
+    ancestors: HashMap<(String, String), bool>,
+}
+
+impl<'a> FileTracker<'a> {
+    fn new(
+        runner: &'a dyn GitRunner,
+        target_repo: &'a str,
+        path: &str,
+        range: &'a TrackingRange,
+        update_option: &'a UpdateOption,
+        progress: &'a ProgressBar,
+    ) -> FileTracker<'a> {
+        FileTracker {
+            runner,
+            target_repo,
+            path: normalize_path(target_repo, path),
+            target_revision: &range.target_revision,
+            from_revision: range.from_revision.as_deref(),
+            since: range.since,
+            tracked_at: &range.tracked_at,
+            update_option,
+            progress,
+            revisions: HashMap::new(),
+            short_revisions: HashMap::new(),
+            blame_cache: HashMap::new(),
+            existing_revisions: HashMap::new(),
+            target_contents: HashMap::new(),
+            commit_times: HashMap::new(),
+            pending_ranges: HashMap::new(),
+            new_paths: Vec::new(),
+            followed_paths: HashMap::new(),
+            source_contents: HashMap::new(),
+            ancestors: HashMap::new(),
+        }
+    }
+
+    // cached git merge-base --is-ancestor of revisions which exist
+    fn is_ancestor(&mut self, revision1: &str, revision2: &str) -> Result<bool> {
+        let key = (revision1.to_string(), revision2.to_string());
+        if let Some(is_ancestor) = self.ancestors.get(&key) {
+            return Ok(*is_ancestor);
+        }
+        let is_ancestor =
+            git_merge_base_is_ancestor_with(self.runner, self.target_repo, revision1, revision2)?;
+        self.ancestors.insert(key, is_ancestor);
+        Ok(is_ancestor)
+    }
+
+    // index of the latest anchored tag whose line refers to the revision (the
+    // revision itself or a descendant of the tag)
+    fn latest_anchored_tag(&mut self, comment: &Comment, revision: &str) -> Result<Option<usize>> {
+        for (index, tag) in comment.tags.iter().enumerate().rev() {
+            if !tag.status.is_anchored() || tag.is_file_level() {
+                continue;
+            }
+            let tag_revision = match normalize_revision(
+                self.runner,
+                self.target_repo,
+                &tag.revision,
+                &mut self.revisions,
+            ) {
+                Ok(tag_revision) => tag_revision,
+                Err(_) => continue,
+            };
+            if tag_revision == revision || self.is_ancestor(&tag_revision, revision)? {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    // the tags of the comment which are tracked: with --from only the latest
+    // anchored tag placed at it (or the latest anchored tag when none is), as
+    // the lines of older tags do not refer to --from; otherwise the tags from
+    // the latest anchored tag on, unless it is on a history unrelated to the
+    // target, since older tags would push stale positions next to the ones it
+    // leads to, e.g. lose a line --fuzz re-anchored again
+    fn tracked_tags(&mut self, comment: &Comment) -> Result<Vec<bool>> {
+        let mut tracked = vec![true; comment.tags.len()];
+        if let Some(from_revision) = self.from_revision {
+            let only = match self.latest_anchored_tag(comment, from_revision)? {
+                Some(index) => Some(index),
+                None => comment
+                    .tags
+                    .iter()
+                    .rposition(|tag| tag.status.is_anchored() && !tag.is_file_level()),
+            };
+            if let Some(only) = only {
+                for (index, tracked) in tracked.iter_mut().enumerate() {
+                    *tracked = index == only;
+                }
+            }
+            return Ok(tracked);
+        }
+        let latest = comment
+            .tags
+            .iter()
+            .rposition(|tag| tag.status.is_anchored() && !tag.is_file_level());
+        let latest_revision = latest.and_then(|latest| {
+            normalize_revision(
+                self.runner,
+                self.target_repo,
+                &comment.tags[latest].revision,
+                &mut self.revisions,
+            )
+            .ok()
+        });
+        if let (Some(latest), Some(revision)) = (latest, latest_revision) {
+            let related = revision == self.target_revision
+                || self.is_ancestor(&revision, self.target_revision)?
+                || self.is_ancestor(self.target_revision, &revision)?;
+            if related {
+                tracked[..latest].fill(false);
+            }
+        }
+        Ok(tracked)
+    }
+
+    // group the lines of the tags by revision so that each revision is blamed once
+    fn plan_ranges(&mut self, comments: &[Comment]) -> Result<()> {
+        for comment in comments {
+            let tracked = self.tracked_tags(comment)?;
+            for (tag, _) in comment
+                .tags
+                .iter()
+                .zip(tracked)
+                .filter(|(_, tracked)| *tracked)
+            {
+                let end_line = tag.end_line.unwrap_or(tag.line);
+                if tag.status == TagStatus::Missing || tag.line < 1 || end_line < tag.line {
+                    continue;
+                }
+                let tag_revision = match self.from_revision {
+                    Some(from_revision) => from_revision.to_string(),
+                    None => match normalize_revision(
+                        self.runner,
+                        self.target_repo,
+                        &tag.revision,
+                        &mut self.revisions,
+                    ) {
+                        Ok(tag_revision) => tag_revision,
+                        Err(_) => continue,
+                    },
+                };
+                self.pending_ranges
+                    .entry(tag_revision)
+                    .or_default()
+                    .push((tag.line, end_line));
+            }
+        }
+        Ok(())
+    }
+
+    // normal blame at the target tells who last changed each tracked line
+    fn record_authors(
+        &self,
+        path: &str,
+        comments: &mut [Comment],
+        new_tag_starts: &[usize],
+    ) -> Result<()> {
+        let new_tags = || {
+            comments
+                .iter()
+                .zip(new_tag_starts)
+                .flat_map(|(comment, start)| &comment.tags[*start..])
+                .filter(|tag| tag.status.is_anchored())
+        };
+        let line_ranges: Vec<(i32, i32)> = new_tags()
+            .map(|tag| (tag.line, tag.end_line.unwrap_or(tag.line)))
+            .collect();
+        if line_ranges.is_empty() {
+            return Ok(());
+        }
+        let git_blame_option = GitBlameOptionBuilder::default()
+            .file(normalize_path(self.target_repo, path))
+            .repo_path(self.target_repo)
+            .line_porcelain(true)
+            .line_ranges(coalesce_line_ranges(line_ranges))
+            .ignore_whitespace(self.update_option.ignore_whitespace)
+            .first_parent(self.update_option.first_parent)
+            .revision(self.target_revision)
+            .build()?;
+        let results = git_blame_reverse_with(self.runner, &git_blame_option)?;
+        for (comment, start) in comments.iter_mut().zip(new_tag_starts) {
+            for tag in &mut comment.tags[*start..] {
+                if !tag.status.is_anchored() {
+                    continue;
+                }
+                // orig_line_number is the line at the blamed revision
+                if let Some(result) = results
+                    .iter()
+                    .find(|result| result.orig_line_number == tag.line)
+                {
+                    tag.author = result.author.clone();
+                    tag.committed_at = result.committed_at.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the line at the offset from the definition of the symbol at the target
+    fn symbol_line(&mut self, symbol: &SymbolAnchor) -> Option<i32> {
+        if !self.target_contents.contains_key(&self.path) {
+            let lines = git_show_file_with(
+                self.runner,
+                self.target_repo,
+                self.target_revision,
+                &self.path,
+            )
+            .unwrap_or_default();
+            self.target_contents.insert(self.path.clone(), lines);
+        }
+        let lines = &self.target_contents[&self.path];
+        let line = find_symbol(lines, &symbol.name).map(|line| line + symbol.offset);
+        verbose!(
+            self.update_option,
+            "symbol {} found at {}: line {:?}",
+            symbol.name,
+            self.target_revision,
+            line
+        );
+        line.filter(|line| *line as usize <= lines.len())
+    }
+
+    // the line within fuzz lines of the expected position of a lost tag which
+    // is the most similar to the line of the tag at its revision
+    fn fuzzy_line(
+        &mut self,
+        tag: &CommentTag,
+        tag_revision: &str,
+        fuzz: usize,
+    ) -> Result<Option<i32>> {
+        // the nearest line above which survived tells how far the lines moved
+        let offset = self.blame_cache[tag_revision]
+            .iter()
+            .filter(|result| {
+                result.orig_line_number <= tag.line
+                    && self.target_revision.starts_with(&result.revision)
+            })
+            .max_by_key(|result| result.orig_line_number)
+            .map_or(0, |result| result.new_line_number - result.orig_line_number);
+        if !self.source_contents.contains_key(tag_revision) {
+            // the file may not exist under its path at the revision
+            let lines = git_show_file_with(self.runner, self.target_repo, tag_revision, &self.path)
+                .unwrap_or_default();
+            self.source_contents.insert(tag_revision.to_string(), lines);
+        }
+        let original = match self.source_contents[tag_revision].get((tag.line - 1) as usize) {
+            // blank lines are similar to every other one
+            Some(original) if !original.trim().is_empty() => original,
+            _ => return Ok(None),
+        };
+        if !self.target_contents.contains_key(&self.path) {
+            // nor at the target, e.g. after a rename
+            let lines = git_show_file_with(
+                self.runner,
+                self.target_repo,
+                self.target_revision,
+                &self.path,
+            )
+            .unwrap_or_default();
+            self.target_contents.insert(self.path.clone(), lines);
+        }
+        let lines = &self.target_contents[&self.path];
+        let expected = tag.line + offset;
+        let fuzz = fuzz as i32;
+        let best = ((expected - fuzz).max(1)..=expected + fuzz)
+            .filter_map(|line| {
+                lines
+                    .get((line - 1) as usize)
+                    .map(|content| (line, line_similarity(original, content)))
+            })
+            .filter(|(_, similarity)| *similarity >= FUZZY_SIMILARITY)
+            // the most similar line, and the nearest one of equally similar lines
+            .max_by(|(line, similarity), (other_line, other_similarity)| {
+                similarity
+                    .total_cmp(other_similarity)
+                    .then_with(|| (other_line - expected).abs().cmp(&(line - expected).abs()))
+            });
+        verbose!(
+            self.update_option,
+            "fuzzy match of {:?} around line {}: {:?}",
+            original,
+            expected,
+            best
+        );
+        Ok(best.map(|(line, _)| line))
+    }
+
+    fn followed_path(&mut self, tag_revision: &str) -> Result<Option<String>> {
+        if let Some(path) = self.followed_paths.get(tag_revision) {
+            return Ok(path.clone());
+        }
+        let renames = git_follow_renames_with(
+            self.runner,
+            self.target_repo,
+            &self.path,
+            tag_revision,
+            self.target_revision,
+        )?;
+        let path = renames.last().map(|rename| rename.from.clone());
+        if path.is_some() {
+            let chain: Vec<String> = renames
+                .iter()
+                .rev()
+                .map(|rename| format!("{} -> {} ({})", rename.from, rename.to, rename.revision))
+                .collect();
+            verbose!(
+                self.update_option,
+                "followed renames since {}: {}",
+                tag_revision,
+                chain.join(", ")
+            );
+        }
+        self.followed_paths
+            .insert(tag_revision.to_string(), path.clone());
+        Ok(path)
+    }
+
+    fn blame_ranges(
+        &self,
+        path: &str,
+        tag_revision: &str,
+        is_ancestor: bool,
+        line_ranges: Vec<(i32, i32)>,
+    ) -> Result<Vec<GitBlameResult>> {
+        let line_ranges = coalesce_line_ranges(line_ranges);
+        let git_blame_option = GitBlameOptionBuilder::default()
+            .file(path)
+            .repo_path(self.target_repo)
+            .reverse(is_ancestor)
+            .line_porcelain(true)
+            .line_ranges(line_ranges.clone())
+            .detect_moves(self.update_option.detect_moves)
+            .detect_copies(self.update_option.detect_copies)
+            .ignore_whitespace(self.update_option.ignore_whitespace)
+            .first_parent(self.update_option.first_parent)
+            .ancestry_path(self.update_option.ancestry_path)
+            .revision(if is_ancestor {
+                format!("{}..{}", tag_revision, self.target_revision)
+            } else {
+                // lines which already exist at the target are
+                // attributed to it as the boundary commit
+                format!("{}..{}", self.target_revision, tag_revision)
+            })
+            .build()?;
+        let results = git_blame_reverse_with(self.runner, &git_blame_option)?;
+        // a single range beyond the end of the file empties the whole output
+        if results.is_empty() && line_ranges.len() > 1 {
+            let mut results = Vec::new();
+            for line_range in line_ranges {
+                results.extend(self.blame_ranges(
+                    path,
+                    tag_revision,
+                    is_ancestor,
+                    vec![line_range],
+                )?);
+            }
+            return Ok(results);
+        }
+        Ok(results)
+    }
+
+    // updates the status of existing tags and returns the tags to append
+    fn advance_comment(&mut self, comment: &mut Comment) -> Result<Vec<CommentTag>> {
+        // tags pushed in this pass are not tracked again
+        let mut new_tags: Vec<CommentTag> = Vec::new();
+        verbose!(self.update_option, "comment text = {}", comment.text);
+        let mut status_updates: Vec<(usize, TagStatus)> = Vec::new();
+        let tracked = self.tracked_tags(comment)?;
+        for (tag_index, tag) in comment.tags.iter().enumerate() {
+            verbose!(self.update_option, "comment text = {:?}", tag);
+            if !tracked[tag_index] {
+                verbose!(self.update_option, "superseded tag skip: {:?}", tag);
+                continue;
+            }
+            if tag.status == TagStatus::Missing {
+                verbose!(self.update_option, "missing tag skip: {:?}", tag);
+                continue;
+            }
+            if tag.is_file_level() {
+                verbose!(self.update_option, "file level tag skip: {:?}", tag);
+                continue;
+            }
+            // there is no commit to blame from
+            if is_zero_revision(&tag.revision) {
+                verbose!(self.update_option, "uncommitted tag skip: {:?}", tag);
+                if tag.status != TagStatus::Uncommitted {
+                    status_updates.push((tag_index, TagStatus::Uncommitted));
+                }
+                continue;
+            }
+            // Unknown tags are checked again since the revision may have been fetched since
+            let exists = match self.existing_revisions.get(&tag.revision) {
+                Some(exists) => *exists,
+                None => {
+                    let exists =
+                        git_revision_exists_with(self.runner, self.target_repo, &tag.revision)?;
+                    self.existing_revisions.insert(tag.revision.clone(), exists);
+                    exists
+                }
+            };
+            if !exists {
+                self.progress.suspend(|| {
+                    warning!("warning: revision {} is not found: {:?}", tag.revision, tag)
+                });
+                if tag.status != TagStatus::Unknown {
+                    status_updates.push((tag_index, TagStatus::Unknown));
+                }
+                continue;
+            }
+            if tag.status == TagStatus::Unknown {
+                status_updates.push((tag_index, TagStatus::Normal));
+            }
+            // --from places the line of the tag tracked with it at that revision instead
+            let tag_revision = match self.from_revision {
+                Some(from_revision) => from_revision.to_string(),
+                None => {
+                    match normalize_revision(
+                        self.runner,
+                        self.target_repo,
+                        &tag.revision,
+                        &mut self.revisions,
+                    ) {
+                        Ok(tag_revision) => tag_revision,
+                        Err(err) => {
+                            verbose!(self.update_option, "unknown revision skip: {:#}", err);
+                            continue;
+                        }
+                    }
+                }
+            };
+            if tag_revision == self.target_revision {
+                verbose!(self.update_option, "same revision skip: {:?}", tag);
+                continue;
+            }
+            if let Some(since) = self.since {
+                if !self.commit_times.contains_key(&tag_revision) {
+                    let commit_time =
+                        git_commit_time_with(self.runner, self.target_repo, &tag_revision)?;
+                    self.commit_times.insert(tag_revision.clone(), commit_time);
+                }
+                if self.commit_times[&tag_revision] < since {
+                    verbose!(self.update_option, "older than --since skip: {:?}", tag);
+                    continue;
+                }
+            }
+            let is_ancestor = git_merge_base_is_ancestor_with(
+                self.runner,
+                self.target_repo,
+                &tag_revision,
+                self.target_revision,
+            )?;
+            verbose!(self.update_option, "is_ancestor = {:?}", is_ancestor);
+            if !is_ancestor {
+                if !git_merge_base_is_ancestor_with(
+                    self.runner,
+                    self.target_repo,
+                    self.target_revision,
+                    &tag_revision,
+                )? {
+                    self.progress.suspend(|| {
+                        warning!(
+                            "warning: revision {} is unrelated to the target, skipped: {:?}",
+                            tag.revision,
+                            tag
+                        )
+                    });
+                    continue;
+                }
+                if !self.update_option.backward {
+                    self.progress.suspend(|| {
+                        warning!(
+                            "warning: revision {} is ahead of the target, skipped (see --backward): {:?}",
+                            tag.revision,
+                            tag
+                        )
+                    });
+                    continue;
+                }
+            }
+            let end_line = tag.end_line.unwrap_or(tag.line);
+            let is_cached = |results: &Vec<GitBlameResult>, line: i32| {
+                results.iter().any(|result| result.orig_line_number == line)
+            };
+            let results = self.blame_cache.entry(tag_revision.clone()).or_default();
+            if 1 <= tag.line
+                && tag.line <= end_line
+                && !(tag.line..=end_line).all(|line| is_cached(results, line))
+            {
+                // the first blame of a revision covers the lines of all tags at it
+                let mut line_ranges = self
+                    .pending_ranges
+                    .remove(&tag_revision)
+                    .unwrap_or_default();
+                line_ranges.push((tag.line, end_line));
+                let mut blame_results =
+                    self.blame_ranges(&self.path, &tag_revision, is_ancestor, line_ranges.clone());
+                if self.update_option.follow && is_ancestor && is_no_such_path(&blame_results) {
+                    if let Some(path) = self.followed_path(&tag_revision)? {
+                        blame_results =
+                            self.blame_ranges(&path, &tag_revision, is_ancestor, line_ranges);
+                    }
+                }
+                let no_such_path = is_no_such_path(&blame_results);
+                let blame_results = match blame_results {
+                    Ok(blame_results) => blame_results,
+                    // tags older than a rename refer to the previous path
+                    Err(_) if no_such_path => {
+                        verbose!(self.update_option, "no such path skip: {:?}", tag);
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+                let results = self.blame_cache.entry(tag_revision.clone()).or_default();
+                for result in blame_results {
+                    verbose!(self.update_option, "{:?}", result);
+                    results.push(result);
+                }
+            }
+            let results = &self.blame_cache[&tag_revision];
+            let last_known = |line: i32| {
+                results
+                    .iter()
+                    .find(|result| result.orig_line_number == line)
+            };
+            // reverse blame reports the last revision which still has the line,
+            // so the line was deleted if it is not the target revision
+            // (and backward blame reports the revision which added the line)
+            let survived = |line: i32| {
+                last_known(line).filter(|result| self.target_revision.starts_with(&result.revision))
+            };
+            // when only one endpoint of a range is gone,
+            // shrink the range to the lines which still exist
+            let mut survived_lines = (tag.line..=end_line).filter_map(survived);
+            let (start, end) = match (survived(tag.line), survived(end_line)) {
+                (Some(start), Some(end)) => (Some(start), Some(end)),
+                (Some(start), None) => (Some(start), survived_lines.next_back()),
+                (None, Some(end)) => (survived_lines.next(), Some(end)),
+                (None, None) => (None, None),
+            };
+            let mut new_tag = if let (Some(start), Some(end)) = (start, end) {
+                if let Some(file_name) = &start.file_name {
+                    self.new_paths.push(file_name.clone());
+                }
+                let new_path = start.file_name.as_ref().unwrap_or(&self.path);
+                if (tag.content_hash.is_some() || tag.symbol.is_some())
+                    && !self.target_contents.contains_key(new_path)
+                {
+                    let lines = git_show_file_with(
+                        self.runner,
+                        self.target_repo,
+                        self.target_revision,
+                        new_path,
+                    )?;
+                    self.target_contents.insert(new_path.clone(), lines);
+                }
+                // blame may follow a line which was actually rewritten
+                let mut status = TagStatus::Normal;
+                if is_zero_revision(&start.revision) {
+                    status = TagStatus::Uncommitted;
+                } else if let Some(content_hash) = &tag.content_hash {
+                    let matched = self.target_contents[new_path]
+                        .get((start.new_line_number - 1) as usize)
+                        .is_some_and(|line| line_content_hash(line) == *content_hash);
+                    if !matched {
+                        verbose!(self.update_option, "content hash mismatch: {:?}", tag);
+                        status = TagStatus::Missing;
+                    }
+                }
+                // the offset follows edits within the definition, and the anchor
+                // is dropped once the line moves out of it
+                let symbol = tag.symbol.as_ref().and_then(|symbol| {
+                    enclosing_symbol(&self.target_contents[new_path], start.new_line_number)
+                        .filter(|anchor| anchor.name == symbol.name)
+                });
+                CommentTag {
+                    revision: abbreviate_revision(
+                        self.runner,
+                        self.target_repo,
+                        &start.revision,
+                        self.update_option,
+                        &mut self.short_revisions,
+                    )?,
+                    line: start.new_line_number,
+                    end_line: tag.end_line.map(|_| end.new_line_number),
+                    status,
+                    content_hash: tag.content_hash.clone(),
+                    author: None,
+                    committed_at: None,
+                    tracked_at: None,
+                    symbol,
+                }
+            } else if let Some(deleted) = last_known(tag.line).filter(|_| is_ancestor) {
+                CommentTag {
+                    revision: abbreviate_revision(
+                        self.runner,
+                        self.target_repo,
+                        &deleted.revision,
+                        self.update_option,
+                        &mut self.short_revisions,
+                    )?,
+                    line: deleted.new_line_number,
+                    end_line: None,
+                    status: TagStatus::Missing,
+                    content_hash: tag.content_hash.clone(),
+                    author: None,
+                    committed_at: None,
+                    tracked_at: None,
+                    symbol: None,
+                }
+            } else {
+                // an empty file or a range past the end of the file blames no lines
+                if (tag.line..=end_line).all(|line| last_known(line).is_none()) {
+                    self.progress.suspend(|| {
+                        warning!(
+                            "warning: git blame output is empty for {} at {}; marking Missing: {:?}",
+                            self.path, tag_revision, tag
+                        )
+                    });
+                }
+                CommentTag {
+                    revision: abbreviate_revision(
+                        self.runner,
+                        self.target_repo,
+                        self.target_revision,
+                        self.update_option,
+                        &mut self.short_revisions,
+                    )?,
+                    line: tag.line,
+                    end_line: tag.end_line,
+                    status: TagStatus::Missing,
+                    content_hash: tag.content_hash.clone(),
+                    author: None,
+                    committed_at: None,
+                    tracked_at: None,
+                    symbol: None,
+                }
+            };
+            if let (Some(symbol), TagStatus::Missing, true) =
+                (&tag.symbol, &new_tag.status, is_ancestor)
+            {
+                if let Some(line) = self.symbol_line(symbol) {
+                    let lines = &self.target_contents[&self.path];
+                    new_tag = CommentTag {
+                        revision: abbreviate_revision(
+                            self.runner,
+                            self.target_repo,
+                            self.target_revision,
+                            self.update_option,
+                            &mut self.short_revisions,
+                        )?,
+                        line,
+                        end_line: tag
+                            .end_line
+                            .map(|end_line| (line + end_line - tag.line).min(lines.len() as i32)),
+                        status: TagStatus::Normal,
+                        content_hash: tag
+                            .content_hash
+                            .as_ref()
+                            .map(|_| line_content_hash(&lines[(line - 1) as usize])),
+                        author: None,
+                        committed_at: None,
+                        tracked_at: None,
+                        symbol: Some(symbol.clone()),
+                    };
+                }
+            }
+            if let (Some(fuzz), TagStatus::Missing, true) =
+                (self.update_option.fuzz, &new_tag.status, is_ancestor)
+            {
+                if let Some(line) = self.fuzzy_line(tag, &tag_revision, fuzz)? {
+                    let lines = &self.target_contents[&self.path];
+                    new_tag = CommentTag {
+                        revision: abbreviate_revision(
+                            self.runner,
+                            self.target_repo,
+                            self.target_revision,
+                            self.update_option,
+                            &mut self.short_revisions,
+                        )?,
+                        line,
+                        end_line: tag
+                            .end_line
+                            .map(|end_line| (line + end_line - tag.line).min(lines.len() as i32)),
+                        status: TagStatus::Fuzzy,
+                        // the hash follows the rewritten line from now on
+                        content_hash: tag
+                            .content_hash
+                            .as_ref()
+                            .map(|_| line_content_hash(&lines[(line - 1) as usize])),
+                        author: None,
+                        committed_at: None,
+                        tracked_at: None,
+                        symbol: None,
+                    };
+                }
+            }
+            if comment
+                .tags
+                .iter()
+                .chain(&new_tags)
+                // a line deleted right after the tag revision is marked Missing
+                // at the same position as the tag itself
+                .any(|tag| tag.is_same_position(&new_tag) && tag.status == new_tag.status)
+            {
+                verbose!(self.update_option, "duplicate tag skip: {:?}", new_tag);
+                continue;
+            }
+            new_tag.tracked_at = Some(self.tracked_at.to_string());
+            new_tags.push(new_tag);
+        }
+        for (tag_index, status) in status_updates {
+            comment.tags[tag_index].status = status;
+        }
+        Ok(new_tags)
+    }
+}
+
+fn update_file_memos(
+    runner: &dyn GitRunner,
+    target_repo: &str,
+    file_data: &mut FileData,
+    range: &TrackingRange,
+    update_option: &UpdateOption,
+    progress: &ProgressBar,
+) -> Result<Option<String>> {
+    verbose!(update_option, "file path = {}", file_data.path);
+    let path = normalize_path(target_repo, &file_data.path);
+    if git_is_symlink_with(runner, target_repo, &range.target_revision, &path)? {
+        progress.suspend(|| {
+            warning!(
+                "warning: {} is a symlink, which is not supported; its memos are left as they are",
+                file_data.path
+            )
+        });
+        progress.inc(file_data.comments.len() as u64);
+        return Ok(None);
+    }
+    let mut tracker = FileTracker::new(
+        runner,
+        target_repo,
+        &file_data.path,
+        range,
+        update_option,
+        progress,
+    );
+    tracker.plan_ranges(&file_data.comments)?;
+    let mut lost = false;
+    // index of the first tag appended to each comment
+    let mut new_tag_starts: Vec<usize> = Vec::new();
+    for comment in &mut file_data.comments {
+        let new_tags = tracker.advance_comment(comment)?;
+        lost |= new_tags
+            .last()
+            .is_some_and(|tag| tag.status == TagStatus::Missing);
+        new_tag_starts.push(comment.tags.len());
+        comment.tags.extend(new_tags);
+        progress.inc(1);
+    }
+    // a deleted file leaves nothing for blame to follow, so memos which
+    // were not marked Missing by blame are marked at the target revision
+    if tracker.new_paths.is_empty()
+        && !git_path_exists_with(runner, target_repo, &range.target_revision, &tracker.path)?
+    {
+        let revision = abbreviate_revision(
+            runner,
+            target_repo,
+            &range.target_revision,
+            update_option,
+            &mut tracker.short_revisions,
+        )?;
+        for comment in &mut file_data.comments {
+            if let Some(tag) = comment.tags.last() {
+                if tag.status.is_anchored()
+                    && !tag.is_file_level()
+                    && !tag.revision.starts_with(&revision)
+                {
+                    let tag = CommentTag {
+                        revision: revision.clone(),
+                        status: TagStatus::Missing,
+                        tracked_at: Some(range.tracked_at.clone()),
+                        ..tag.clone()
+                    };
+                    comment.tags.push(tag);
+                    lost = true;
+                }
+            }
+        }
+        if update_option.verbose {
+            eprint!("{}", advanced_tags_table(file_data, &new_tag_starts));
+        }
+        return Ok(if lost {
+            Some(file_data.path.clone())
+        } else {
+            None
+        });
+    }
+    // follow the file when blame reports the lines now live under another path
+    let new_paths = &tracker.new_paths;
+    if let Some(new_path) = new_paths.first() {
+        if new_paths.iter().any(|path| path != new_path) {
+            progress.suspend(|| {
+                warning!(
+                    "warning: memos of {} moved to several files: {}",
+                    file_data.path,
+                    new_paths.join(", ")
+                )
+            });
+        } else if *new_path != tracker.path {
+            progress.suspend(|| warning!("renamed: {} -> {}", file_data.path, new_path));
+            file_data.path = new_path.clone();
+        }
+    }
+    if update_option.record_author {
+        tracker.record_authors(&file_data.path, &mut file_data.comments, &new_tag_starts)?;
+    }
+    if update_option.verbose {
+        eprint!("{}", advanced_tags_table(file_data, &new_tag_starts));
+    }
+    Ok(None)
+}
+
+// the previous and the latest tag of each comment which gained tags,
+// built at once so that the tables of files updated in parallel do not mix
+fn advanced_tags_table(file_data: &FileData, new_tag_starts: &[usize]) -> String {
+    let position = |tag: &CommentTag| format!("{}@{}", tag.line_span(), tag.revision);
+    let mut rows = vec![[
+        String::from("old"),
+        String::from("new"),
+        String::from("status"),
+        String::from("memo"),
+    ]];
+    for (comment, start) in file_data.comments.iter().zip(new_tag_starts) {
+        let (old_tags, new_tags) = comment.tags.split_at(*start);
+        if let (Some(old_tag), Some(new_tag)) = (old_tags.last(), new_tags.last()) {
+            rows.push([
+                position(old_tag),
+                position(new_tag),
+                new_tag.status.to_string(),
+                comment.text.replace('\n', " "),
+            ]);
+        }
+    }
+    if rows.len() == 1 {
+        return String::new();
+    }
+    let width = |column: usize| rows.iter().map(|row| row[column].len()).max().unwrap_or(0);
+    let (old_width, new_width, status_width) = (width(0), width(1), width(2));
+    let mut table = format!("advanced tags of {}:\n", file_data.path);
+    for [old, new, status, text] in &rows {
+        table.push_str(&format!(
+            "  {:old_width$}  {:new_width$}  {:status_width$}  {}\n",
+            old, new, status, text
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use runner::{FakeGit, GitOutput};
+
+    fn sample_data() -> RootData {
+        RootData {
+            version: ROOT_DATA_VERSION,
+            files: vec![FileData {
+                path: String::from("./README.md"),
+                repo: None,
+                comments: vec![
+                    Comment {
+                        text: String::from("hello A"),
+                        tags: vec![CommentTag {
+                            revision: String::from("39690ed"),
+                            line: 1,
+                            end_line: None,
+                            status: TagStatus::Normal,
+                            content_hash: None,
+                            author: None,
+                            committed_at: None,
+                            tracked_at: None,
+                            symbol: None,
+                        }],
+                        meta: BTreeMap::new(),
+                    },
+                    Comment {
+                        text: String::from("hello B"),
+                        tags: vec![CommentTag {
+                            revision: String::from("39690ed"),
+                            line: 2,
+                            end_line: None,
+                            status: TagStatus::Normal,
+                            content_hash: None,
+                            author: None,
+                            committed_at: None,
+                            tracked_at: None,
+                            symbol: None,
+                        }],
+                        meta: BTreeMap::new(),
+                    },
+                ],
+            }],
+        }
+    }
+
+    #[test]
+    fn memo_files_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = sample_data();
+        for name in ["memo.json", "memo.yaml", "memo.json.gz"] {
+            let path = dir.path().join(name);
+            let path = path.to_str().unwrap();
+            save_root_data(path, &data, None).unwrap();
+            let loaded = load_root_data(path, None).unwrap();
+            assert_eq!(
+                serde_json::to_value(&loaded).unwrap(),
+                serde_json::to_value(&data).unwrap(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn new_from_line_parses_blame_lines() {
+        let re = Regex::new(BLAME_LINE_PATTERN).unwrap();
+        // (line, revision, new_line_number, orig_line_number, boundary)
+        let cases = [
+            (
+                "1a2b3c4 5 (Alice 2022-03-01 12:34:56 +0900 7) let a = 1;",
+                "1a2b3c4",
+                5,
+                7,
+                false,
+            ),
+            (
+                "1a2b3c4  8 (Alice 2022-03-01 12:34:56 +0900  9) padded",
+                "1a2b3c4",
+                8,
+                9,
+                false,
+            ),
+            (
+                "1a2b3c4 123 (Alice 2022-03-01 12:34:56 +0900 4567) multi-digit",
+                "1a2b3c4",
+                123,
+                4567,
+                false,
+            ),
+            (
+                "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b 2 (Alice 2022-03-01 12:34:56 -0500 3) full hash",
+                "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b",
+                2,
+                3,
+                false,
+            ),
+            (
+                "1a2b 1 (Mary Ann Smith 2022-03-01 12:34:56 +0000 1) short hash",
+                "1a2b",
+                1,
+                1,
+                false,
+            ),
+            (
+                "1a2b3c4 4 (A (b) c 2022-03-01 12:34:56 +0000 6) call(x)",
+                "1a2b3c4",
+                4,
+                6,
+                false,
+            ),
+            (
+                "1a2b3c4 4 (Bob) 2022-03-01 12:34:56 +0000 6) f(1 2)",
+                "1a2b3c4",
+                4,
+                6,
+                false,
+            ),
+            (
+                "^1a2b3c  10 (Alice 2022-03-01 12:34:56 +0900 10) boundary",
+                "1a2b3c",
+                10,
+                10,
+                true,
+            ),
+        ];
+        for (line, revision, new_line_number, orig_line_number, boundary) in cases {
+            let result = GitBlameResult::new_from_line(&re, line)
+                .unwrap_or_else(|err| panic!("{}: {:#}", line, err));
+            assert_eq!(result.revision, revision, "{}", line);
+            assert_eq!(result.new_line_number, new_line_number, "{}", line);
+            assert_eq!(result.orig_line_number, orig_line_number, "{}", line);
+            assert_eq!(result.boundary, boundary, "{}", line);
+        }
+    }
+
+    #[test]
+    fn new_from_line_rejects_other_lines() {
+        let re = Regex::new(BLAME_LINE_PATTERN).unwrap();
+        for line in [
+            "",
+            "1a2b3c4",
+            "1a2b3c4 x (Alice 2022-03-01 12:34:56 +0900 7) x",
+        ] {
+            assert!(
+                GitBlameResult::new_from_line(&re, line).is_err(),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn new_from_porcelain_header_parses_headers() {
+        // (header, revision, new_line_number, orig_line_number)
+        let cases = [
+            (
+                "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b 1 1 1",
+                "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b",
+                1,
+                1,
+            ),
+            (
+                "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b 12 345",
+                "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b",
+                12,
+                345,
+            ),
+            ("1a2b3c4 1024 7 3", "1a2b3c4", 1024, 7),
+        ];
+        for (line, revision, new_line_number, orig_line_number) in cases {
+            let result = GitBlameResult::new_from_porcelain_header(line)
+                .unwrap_or_else(|err| panic!("{}: {:#}", line, err));
+            assert_eq!(result.revision, revision, "{}", line);
+            assert_eq!(result.new_line_number, new_line_number, "{}", line);
+            assert_eq!(result.orig_line_number, orig_line_number, "{}", line);
+        }
+        for line in ["1a2b3c4", "1a2b3c4 1", "1a2b3c4 x 1"] {
+            assert!(
+                GitBlameResult::new_from_porcelain_header(line).is_err(),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn new_from_porcelain_reads_headers_and_boundaries() {
+        let output = [
+            "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b 3 10 2",
+            "author A (b) c",
+            "boundary",
+            "filename src/lib.rs",
+            "\tfirst",
+            "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b 4 11",
+            "author A (b) c",
+            "filename src/lib.rs",
+            "\tsecond",
+            "9f8e7d6c5b4a39281706f5e4d3c2b1a098765432 120 1200 1",
+            "author Bob",
+            "filename src/main.rs",
+            "\tthird",
+        ]
+        .map(String::from);
+        let results = GitBlameResult::new_from_porcelain(output.into_iter());
+        let summary: Vec<(&str, i32, i32, bool, Option<&str>)> = results
+            .iter()
+            .map(|result| {
+                (
+                    &result.revision[..7],
+                    result.new_line_number,
+                    result.orig_line_number,
+                    result.boundary,
+                    result.file_name.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("1a2b3c4", 3, 10, true, Some("src/lib.rs")),
+                ("1a2b3c4", 4, 11, false, Some("src/lib.rs")),
+                ("9f8e7d6", 120, 1200, false, Some("src/main.rs")),
+            ]
+        );
+        assert_eq!(results[0].author.as_deref(), Some("A (b) c"));
+    }
+
+    const OLD: &str = "1111111111111111111111111111111111111111";
+    const NEW: &str = "2222222222222222222222222222222222222222";
+
+    fn git_ok(stdout: &str) -> GitOutput {
+        GitOutput::new(0, stdout, "")
+    }
+
+    fn normal_tag(revision: &str, line: i32) -> CommentTag {
+        CommentTag {
+            revision: revision.to_string(),
+            line,
+            end_line: None,
+            status: TagStatus::Normal,
+            content_hash: None,
+            author: None,
+            committed_at: None,
+            tracked_at: None,
+            symbol: None,
+        }
+    }
+
+    #[test]
+    fn build_args_follow_the_options() {
+        let option = GitBlameOptionBuilder::default()
+            .repo_path("repo")
+            .reverse(true)
+            .line_porcelain(true)
+            .line_ranges(vec![(2, 3), (7, 7)])
+            .detect_moves(true)
+            .detect_copies(true)
+            .ignore_whitespace(true)
+            .first_parent(true)
+            .ancestry_path(true)
+            .revision(format!("{}..{}", OLD, NEW))
+            .file("a.txt")
+            .build()
+            .unwrap();
+        let range = format!("{}..{}", OLD, NEW);
+        assert_eq!(
+            option.build_args(),
+            [
+                "-C",
+                "repo",
+                "blame",
+                "--reverse",
+                "--line-porcelain",
+                "-L",
+                "2,3",
+                "-L",
+                "7,7",
+                "-M",
+                "-C",
+                "-w",
+                "--first-parent",
+                "--ancestry-path",
+                range.as_str(),
+                "a.txt",
+            ]
+        );
+    }
+
+    #[test]
+    fn git_blame_reverse_with_parses_the_runner_output() {
+        let option = GitBlameOptionBuilder::default()
+            .repo_path("repo")
+            .reverse(true)
+            .line_ranges(vec![(2, 3)])
+            .revision(format!("{}..{}", OLD, NEW))
+            .file("a.txt")
+            .build()
+            .unwrap();
+        let output = format!(
+            "{} 5 (A (b) c 2022-03-01 12:00:00 +0900 2) two\n^{} 3 (A (b) c 2022-03-01 12:00:00 +0900 3) three\n",
+            &NEW[..7],
+            &OLD[..7]
+        );
+        let args = option.build_args();
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let git = FakeGit::default().respond(&args, git_ok(&output));
+        let results = git_blame_reverse_with(&git, &option).unwrap();
+        let lines: Vec<(&str, i32, i32, bool)> = results
+            .iter()
+            .map(|result| {
+                (
+                    result.revision.as_str(),
+                    result.new_line_number,
+                    result.orig_line_number,
+                    result.boundary,
+                )
+            })
+            .collect();
+        assert_eq!(lines, [(&NEW[..7], 5, 2, false), (&OLD[..7], 3, 3, true)]);
+        assert_eq!(git.calls().len(), 1);
+    }
+
+    // what tracking line 2 of a.txt from 1111111 to NEW runs, NEW being a
+    // descendant of 1111111, with the reverse blame output of the line
+    fn tracking_git(blame: &str) -> FakeGit {
+        let range = format!("{}..{}", OLD, NEW);
+        FakeGit::default()
+            .respond(
+                &["-C", "repo", "rev-parse", "1111111"],
+                git_ok(&format!("{}\n", OLD)),
+            )
+            .respond(
+                &["-C", "repo", "cat-file", "-e", "1111111^{commit}"],
+                git_ok(""),
+            )
+            .respond(
+                &["-C", "repo", "merge-base", "--is-ancestor", OLD, NEW],
+                git_ok(""),
+            )
+            .respond(
+                &["-C", "repo", "merge-base", "--is-ancestor", NEW, OLD],
+                GitOutput::new(1, "", ""),
+            )
+            .respond(
+                &[
+                    "-C",
+                    "repo",
+                    "blame",
+                    "--reverse",
+                    "--line-porcelain",
+                    "-L",
+                    "2,2",
+                    &range,
+                    "a.txt",
+                ],
+                git_ok(blame),
+            )
+            .respond(
+                &["-C", "repo", "rev-parse", "--short", NEW],
+                git_ok("2222222\n"),
+            )
+    }
+
+    // the tags advance_comment pushes for the tag, and the comment afterwards
+    fn advance(git: &FakeGit, tag: CommentTag) -> (Vec<CommentTag>, Comment) {
+        let option = UpdateOptionBuilder::default().build().unwrap();
+        let range = TrackingRange {
+            target_revision: NEW.to_string(),
+            from_revision: None,
+            since: None,
+            tracked_at: String::from("2022-03-01T00:00:00Z"),
+        };
+        let progress = ProgressBar::hidden();
+        let mut tracker = FileTracker::new(git, "repo", "a.txt", &range, &option, &progress);
+        let mut comment = Comment {
+            text: String::from("two"),
+            tags: vec![tag],
+            meta: BTreeMap::new(),
+        };
+        tracker.plan_ranges(std::slice::from_ref(&comment)).unwrap();
+        let new_tags = tracker.advance_comment(&mut comment).unwrap();
+        (new_tags, comment)
+    }
+
+    #[test]
+    fn advance_comment_moves_the_tag_to_the_line_blame_reports() {
+        let git = tracking_git(&format!("{} 3 2 1\nfilename a.txt\n\ttwo\n", NEW));
+        let (new_tags, _) = advance(&git, normal_tag("1111111", 2));
+        let new_tags: Vec<(&str, i32, &TagStatus)> = new_tags
+            .iter()
+            .map(|tag| (tag.revision.as_str(), tag.line, &tag.status))
+            .collect();
+        assert_eq!(new_tags, [("2222222", 3, &TagStatus::Normal)]);
+    }
+
+    #[test]
+    fn advance_comment_marks_deleted_lines_missing() {
+        // the line was last seen at 1111111, which the Missing tag is stamped with
+        let git = tracking_git(&format!("{} 2 2 1\nfilename a.txt\n\ttwo\n", OLD)).respond(
+            &["-C", "repo", "rev-parse", "--short", OLD],
+            git_ok("1111111\n"),
+        );
+        let (new_tags, _) = advance(&git, normal_tag("1111111", 2));
+        let new_tags: Vec<(&str, i32, &TagStatus)> = new_tags
+            .iter()
+            .map(|tag| (tag.revision.as_str(), tag.line, &tag.status))
+            .collect();
+        assert_eq!(new_tags, [("1111111", 2, &TagStatus::Missing)]);
+    }
+
+    #[test]
+    fn advance_comment_marks_tags_of_unknown_revisions() {
+        let git = FakeGit::default()
+            .respond(
+                &["-C", "repo", "rev-parse", "1111111"],
+                GitOutput::new(128, "", "fatal: ambiguous argument '1111111'"),
+            )
+            .respond(
+                &["-C", "repo", "cat-file", "-e", "1111111^{commit}"],
+                GitOutput::new(128, "", "fatal: Not a valid object name 1111111^{commit}"),
+            );
+        let (new_tags, comment) = advance(&git, normal_tag("1111111", 2));
+        assert!(new_tags.is_empty());
+        assert_eq!(comment.tags[0].status, TagStatus::Unknown);
+        assert!(!git
+            .calls()
+            .iter()
+            .any(|args| args.iter().any(|arg| arg == "blame")));
+    }
+
+    #[test]
+    fn update_memos_with_runs_git_through_the_runner() {
+        let git = tracking_git(&format!("{} 3 2 1\nfilename a.txt\n\ttwo\n", NEW))
+            .respond(
+                &["-C", "repo", "rev-parse", "HEAD"],
+                git_ok(&format!("{}\n", NEW)),
+            )
+            .respond(
+                &["-C", "repo", "ls-tree", NEW, "--", "a.txt"],
+                git_ok("100644 blob 0123456789abcdef0123456789abcdef01234567\ta.txt\n"),
+            );
+        let data = RootData {
+            version: ROOT_DATA_VERSION,
+            files: vec![FileData {
+                path: String::from("a.txt"),
+                repo: None,
+                comments: vec![Comment {
+                    text: String::from("two"),
+                    tags: vec![normal_tag("1111111", 2)],
+                    meta: BTreeMap::new(),
+                }],
+            }],
+        };
+        let option = UpdateOptionBuilder::default().build().unwrap();
+        // any command without canned output fails the run
+        let data = update_memos_with(&git, "repo", data, &option).unwrap();
+        let tag = data.files[0].comments[0].tags.last().unwrap();
+        assert_eq!(
+            (tag.revision.as_str(), tag.line, &tag.status),
+            ("2222222", 3, &TagStatus::Normal)
+        );
+        assert!(git
+            .calls()
+            .iter()
+            .any(|args| args.iter().any(|arg| arg == "blame")));
+    }
+
+    #[test]
+    fn git_current_revision_has_no_trailing_whitespace() {
+        for stdout in ["2222222\n", "2222222\r\n", "2222222 \n\n"] {
+            let git = FakeGit::default().respond(
+                &["-C", "repo", "rev-parse", "--short", "HEAD"],
+                git_ok(stdout),
+            );
+            let revision =
+                git_current_revision_with(&git, "repo", RevisionFormat::Short, None).unwrap();
+            assert_eq!(revision, "2222222", "{:?}", stdout);
+        }
+    }
+
+    #[test]
+    fn find_hunk_with_takes_the_worktree_then_the_index_hunk() {
+        let git = |worktree: &str, index: &str| {
+            FakeGit::default()
+                .respond(
+                    &["-C", "repo", "rev-parse", "HEAD"],
+                    git_ok(&format!("{}\n", NEW)),
+                )
+                .respond(
+                    &["-C", "repo", "diff", "-U0", "--", "a.txt"],
+                    git_ok(worktree),
+                )
+                .respond(
+                    &["-C", "repo", "diff", "--cached", "-U0", "--", "a.txt"],
+                    git_ok(index),
+                )
+        };
+        let worktree = "@@ -3 +3,2 @@\n-c\n+x\n+y\n";
+        let index = "@@ -0,0 +1 @@\n+top\n";
+        assert_eq!(
+            find_hunk_with(&git(worktree, index), "repo", "HEAD", "a.txt").unwrap(),
+            (ZERO_REVISION.to_string(), 3, 4)
+        );
+        assert_eq!(
+            find_hunk_with(&git("", index), "repo", "HEAD", "a.txt").unwrap(),
+            (ZERO_REVISION.to_string(), 1, 1)
+        );
+    }
+
+    #[test]
+    fn git_is_detached_head_with_reads_the_exit_code() {
+        let git = |exit_code: i32| {
+            FakeGit::default().respond(
+                &["-C", "repo", "symbolic-ref", "-q", "HEAD"],
+                GitOutput::new(exit_code, "", ""),
+            )
+        };
+        assert!(!git_is_detached_head_with(&git(0), "repo").unwrap());
+        assert!(git_is_detached_head_with(&git(1), "repo").unwrap());
+        assert!(git_is_detached_head_with(&git(128), "repo").is_err());
+    }
+
+    #[test]
+    fn build_args_add_n_unless_porcelain_output_does_without_it() {
+        // (line_number, line_porcelain, arguments after "blame")
+        let cases: [(bool, bool, &[&str]); 4] = [
+            (false, false, &["-n", "HEAD", "a.txt"]),
+            (true, false, &["-n", "HEAD", "a.txt"]),
+            (false, true, &["--line-porcelain", "HEAD", "a.txt"]),
+            (true, true, &["-n", "--line-porcelain", "HEAD", "a.txt"]),
+        ];
+        for (line_number, line_porcelain, expected) in cases {
+            let option = GitBlameOptionBuilder::default()
+                .repo_path("repo")
+                .line_number(line_number)
+                .line_porcelain(line_porcelain)
+                .revision("HEAD")
+                .file("a.txt")
+                .build()
+                .unwrap();
+            assert_eq!(
+                option.build_args()[3..],
+                *expected,
+                "line_number={} line_porcelain={}",
+                line_number,
+                line_porcelain
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_path_makes_paths_relative_to_the_root() {
+        // (repository root, path, normalized path)
+        let cases = [
+            ("/work/repo", "./foo", "foo"),
+            ("/work/repo", "foo", "foo"),
+            ("/work/repo", "dir/foo", "dir/foo"),
+            ("/work/repo", "./dir/../foo", "foo"),
+            ("/work/repo", "/work/repo/dir/foo", "dir/foo"),
+            // --repo out of the current directory
+            ("../other/repo", "../other/repo/dir/foo", "dir/foo"),
+            ("/other/repo", "/other/repo/./foo", "foo"),
+            // absolute paths out of the root are kept
+            ("/work/repo", "/work/elsewhere/foo", "/work/elsewhere/foo"),
+        ];
+        for (repo_root, path, expected) in cases {
+            assert_eq!(
+                normalize_path(repo_root, path),
+                expected,
+                "{} in {}",
+                path,
+                repo_root
+            );
+        }
+    }
+
+    #[test]
+    fn blame_output_with_crlf_line_endings_is_parsed() {
+        let re = Regex::new(BLAME_LINE_PATTERN).unwrap();
+        let result = GitBlameResult::new_from_line(
+            &re,
+            "1a2b3c4 5 (Alice 2022-03-01 12:34:56 +0900 7) let a = 1;\r",
+        )
+        .unwrap();
+        assert_eq!(
+            (
+                result.revision.as_str(),
+                result.new_line_number,
+                result.orig_line_number
+            ),
+            ("1a2b3c4", 5, 7)
+        );
+
+        let output = format!(
+            "{} 5 7 1\r\nauthor Alice\r\nboundary\r\nfilename a.txt\r\n\tlet a = 1;\r\n",
+            OLD
+        );
+        let results = GitBlameResult::new_from_porcelain(output.lines().map(String::from));
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].revision, OLD);
+        assert_eq!(results[0].orig_line_number, 7);
+        assert_eq!(results[0].author.as_deref(), Some("Alice"));
+        assert_eq!(results[0].file_name.as_deref(), Some("a.txt"));
+        assert!(results[0].boundary);
+    }
+
+    #[test]
+    fn boundary_lines_keep_the_revision_without_the_caret() {
+        let re = Regex::new(BLAME_LINE_PATTERN).unwrap();
+        let result = GitBlameResult::new_from_line(
+            &re,
+            "^2222222 3 (Alice 2022-03-01 12:34:56 +0900 2) two",
+        )
+        .unwrap();
+        assert!(result.boundary);
+        assert_eq!(result.revision, "2222222");
+        assert!(NEW.starts_with(&result.revision));
+
+        // a boundary at the target is a line which survived
+        let git = tracking_git(&format!("{} 3 2 1\nboundary\nfilename a.txt\n\ttwo\n", NEW));
+        let (new_tags, _) = advance(&git, normal_tag("1111111", 2));
+        let new_tags: Vec<(i32, &TagStatus)> =
+            new_tags.iter().map(|tag| (tag.line, &tag.status)).collect();
+        assert_eq!(new_tags, [(3, &TagStatus::Normal)]);
+    }
+
+    #[test]
+    fn tag_status_names_agree_in_serde_and_strum() {
+        let statuses = [
+            TagStatus::Normal,
+            TagStatus::Missing,
+            TagStatus::Unknown,
+            TagStatus::Fuzzy,
+            TagStatus::Uncommitted,
+        ];
+        for status in statuses {
+            // a new variant fails to compile here until it is added above
+            match status {
+                TagStatus::Normal
+                | TagStatus::Missing
+                | TagStatus::Unknown
+                | TagStatus::Fuzzy
+                | TagStatus::Uncommitted => {}
+            }
+            let serialized = serde_json::to_string(&status).unwrap();
+            let name = status.to_string();
+            assert_eq!(serialized, format!("\"{}\"", name));
+            assert_eq!(name, status.as_ref());
+            assert_eq!(name.parse::<TagStatus>().unwrap(), status);
+            assert_eq!(
+                serde_json::from_str::<TagStatus>(&serialized).unwrap(),
+                status
+            );
+        }
+    }
+
+    // (path, text) of the memos filter_memos keeps
+    fn filtered(filter: &MemoFilter) -> Vec<(String, String)> {
+        let comment =
+            |text: &str, revision: &str, status: TagStatus, meta: &[(&str, &str)]| Comment {
+                text: text.to_string(),
+                tags: vec![CommentTag {
+                    status,
+                    ..normal_tag(revision, 1)
+                }],
+                meta: meta
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            };
+        let data = RootData {
+            version: ROOT_DATA_VERSION,
+            files: vec![
+                FileData {
+                    path: String::from("src/a.rs"),
+                    repo: None,
+                    comments: vec![
+                        comment(
+                            "high",
+                            "1111111",
+                            TagStatus::Normal,
+                            &[("priority", "high")],
+                        ),
+                        comment("low", "2222222", TagStatus::Missing, &[("priority", "low")]),
+                    ],
+                },
+                FileData {
+                    path: String::from("./docs/b.md"),
+                    repo: None,
+                    comments: vec![comment("doc", "1111111", TagStatus::Normal, &[])],
+                },
+            ],
+        };
+        filter_memos("/repo", &data, filter)
+            .files
+            .iter()
+            .flat_map(|file_data| {
+                file_data
+                    .comments
+                    .iter()
+                    .map(|comment| (file_data.path.clone(), comment.text.clone()))
+            })
+            .collect()
+    }
+
+    fn memo(path: &str, text: &str) -> (String, String) {
+        (path.to_string(), text.to_string())
+    }
+
+    #[test]
+    fn filter_memos_checks_each_field() {
+        assert_eq!(filtered(&MemoFilter::default()).len(), 3);
+        let meta = MemoFilter {
+            meta: vec![(String::from("priority"), String::from("high"))],
+            ..MemoFilter::default()
+        };
+        assert_eq!(filtered(&meta), [memo("src/a.rs", "high")]);
+        let status = MemoFilter {
+            status: Some(TagStatus::Missing),
+            ..MemoFilter::default()
+        };
+        assert_eq!(filtered(&status), [memo("src/a.rs", "low")]);
+        // the full hash of the filter starts with the abbreviated revision of the tag
+        let revision = MemoFilter {
+            revision: Some(OLD.to_string()),
+            ..MemoFilter::default()
+        };
+        assert_eq!(
+            filtered(&revision),
+            [memo("src/a.rs", "high"), memo("./docs/b.md", "doc")]
+        );
+        let path_glob = MemoFilter {
+            path_glob: Some(Glob::new("docs/*.md").unwrap().compile_matcher()),
+            ..MemoFilter::default()
+        };
+        assert_eq!(filtered(&path_glob), [memo("./docs/b.md", "doc")]);
+        // every field must hold
+        let both = MemoFilter {
+            status: Some(TagStatus::Normal),
+            ..meta
+        };
+        assert_eq!(filtered(&both), [memo("src/a.rs", "high")]);
+        let none = MemoFilter {
+            status: Some(TagStatus::Missing),
+            ..both
+        };
+        assert!(filtered(&none).is_empty());
+    }
+}
@@ -1,270 +1,1162 @@
-use anyhow::{anyhow, Result};
-use regex::Regex;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::Write;
-use std::process::Command;
-use std::process::Stdio;
+use anyhow::{anyhow, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use git_memo::config::Config;
+use git_memo::notes::GitNotesStore;
+use git_memo::runner::SystemGit;
+use git_memo::store::{DirStore, JsonFileStore, MemoStore};
+use git_memo::warning;
+use git_memo::{
+    add_memo, configure_git, diagnose_memos, diff_update, filter_memos, find_hunk, find_line,
+    gc_memos, git_commit_index, git_commit_revision, git_current_revision, git_is_detached_head,
+    git_rev_parse, git_show_toplevel, locate_in_worktree, lost_memos, memo_stats, merge_root_data,
+    migrate_memos, normalize_path, remove_memo, set_quiet, sort_memos, split_root_data,
+    summarize_update, update_memos, CommentTag, GitBlameResult, GitSettings, MemoBackend,
+    MemoFilter, MemoFormat, RevisionFormat, RootData, TagResolver, TagStatus, UpdateOption,
+    UpdateOptionBuilder,
+};
+use globset::Glob;
+use owo_colors::{OwoColorize, Style};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::Duration;
+use strum_macros::EnumString;
 
-#[macro_use]
-extern crate serde_derive;
+#[derive(Parser, Debug, Clone)]
+#[clap(
+    version,
+    about = "Track memos attached to lines of files in a git repository",
+    after_help = "--repo, --input, --output, --format and --abbrev are resolved in the order: \
+                  built-in defaults < .gitmemo.toml at the repository root < environment variables < command line flags"
+)]
+struct Args {
+    /// Path of the target git repository [default: root of the repository containing the current directory]
+    #[clap(long, global = true, env = "GIT_MEMO_REPO")]
+    repo: Option<String>,
 
-#[macro_use]
-extern crate derive_builder;
+    /// Memo file to read ('-' for stdin, gzip-compressed when ending with .gz) [default: in.json]
+    #[clap(long, global = true, env = "GIT_MEMO_INPUT")]
+    input: Option<String>,
 
-use strum_macros::EnumString;
-#[derive(EnumString, Serialize, Deserialize, Debug, PartialEq, Clone)]
-enum TagStatus {
-    Normal,
-    Missing,
+    /// Memo file to write the tracked result to ('-' for stdout, gzip-compressed when ending with .gz) [default: out.json]
+    #[clap(long, global = true, env = "GIT_MEMO_OUTPUT")]
+    output: Option<String>,
+
+    /// Read and merge every memo file matching this glob instead of --input; tracking writes each memo back to the file it came from unless an output is given
+    #[clap(long, global = true)]
+    input_glob: Option<String>,
+
+    /// Write one memo file per annotated file under this directory instead of --output, read back by passing the directory as --input
+    #[clap(long, global = true, conflicts_with = "output")]
+    output_dir: Option<String>,
+
+    /// Number of hex digits of revisions stamped on new tags
+    #[clap(long, global = true, env = "GIT_MEMO_ABBREV")]
+    abbrev: Option<usize>,
+
+    /// Revisions stamped on new tags: short (see --abbrev) or long (full object names)
+    #[clap(long, global = true, default_value = "short")]
+    revision_format: RevisionFormat,
+
+    /// Memo file format: json or yaml [default: guessed from the file extension]
+    #[clap(long, global = true, env = "GIT_MEMO_FORMAT")]
+    format: Option<MemoFormat>,
+
+    /// Sort files by path and memos by line when writing memo files
+    #[clap(long, global = true)]
+    sort: bool,
+
+    /// Copy a memo file to <name>.bak before overwriting it
+    #[clap(long, global = true)]
+    backup: bool,
+
+    /// Log each tracking step on stderr
+    #[clap(long, global = true)]
+    verbose: bool,
+
+    /// Print no warnings, notes or progress bar on stderr
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Path or name of the git executable
+    #[clap(long, global = true, env = "GIT_MEMO_GIT", default_value = "git")]
+    git_binary: String,
+
+    /// Kill a git command running longer than this many seconds
+    #[clap(long, global = true)]
+    timeout: Option<u64>,
+
+    /// Run a git command which failed to start or timed out again up to this many times
+    #[clap(long, global = true, default_value = "0")]
+    retries: usize,
+
+    /// Where memos are stored: json (the memo files) or git-notes (refs/notes/git-memo)
+    #[clap(long, global = true, default_value = "json")]
+    backend: MemoBackend,
+
+    /// Revision the lines of all tags are read at, instead of the revision of each tag
+    #[clap(long, global = true)]
+    from: Option<String>,
+
+    /// Revision to track memos forward to
+    #[clap(long, global = true, default_value = "HEAD")]
+    to: String,
+
+    /// Maximum number of files tracked in parallel [default: number of CPUs]
+    #[clap(long, global = true)]
+    jobs: Option<usize>,
+
+    /// Track memos to the staged content without writing, exiting with 1 if any memo would lose its line (for pre-commit hooks)
+    #[clap(long, global = true)]
+    check: bool,
+
+    /// Report what would change without writing, exiting with 1 if anything would change
+    #[clap(long, global = true)]
+    dry_run: bool,
+
+    /// Print the old and new position of each memo on stderr after tracking
+    #[clap(long, global = true)]
+    diff: bool,
+
+    /// Exit with 2 after writing if any memo newly lost its line
+    #[clap(long, global = true)]
+    fail_on_missing: bool,
+
+    /// Detect lines moved within a file (git blame -M)
+    #[clap(long, global = true)]
+    detect_moves: bool,
+
+    /// Detect lines copied from other files (git blame -C)
+    #[clap(long, global = true)]
+    detect_copies: bool,
+
+    /// Leave memos of files matching this glob as they are, in addition to `ignore` of .gitmemo.toml
+    #[clap(long, global = true, multiple_occurrences = true)]
+    ignore: Vec<String>,
+
+    /// Ignore whitespace changes so that reindented lines keep their revision (git blame -w)
+    #[clap(long, global = true)]
+    ignore_whitespace: bool,
+
+    /// Follow only the first parent of merges, so lines of squashed or merged branches belong to the mainline (git blame --first-parent)
+    #[clap(long, global = true)]
+    first_parent: bool,
+
+    /// Only follow commits on a path from the revision of a tag to the target, leaving out churn of unrelated side branches (git blame --ancestry-path)
+    #[clap(long, global = true)]
+    ancestry_path: bool,
+
+    /// Also track tags whose revision is a descendant of --to back to it
+    #[clap(long, global = true)]
+    backward: bool,
+
+    /// Blame the path a file had at the revision of a tag, found by git log --follow, when the file does not exist there under its memo path
+    #[clap(long, global = true)]
+    follow: bool,
+
+    /// Re-anchor a memo whose line is lost to the most similar line within this many lines of where it is expected, marking it Fuzzy
+    #[clap(long, global = true)]
+    fuzz: Option<usize>,
+
+    /// Record the author and date of the last change to each tracked line
+    #[clap(long, global = true)]
+    record_author: bool,
+
+    /// Only track tags whose revision was committed after this git date (e.g. "2 weeks ago")
+    #[clap(long, global = true)]
+    since: Option<String>,
+
+    /// Track the memo file of each `repo_path<TAB>memo_file` line of stdin in place, continuing past failures
+    #[clap(long, global = true, conflicts_with_all = &["input-glob", "check"])]
+    stdin_repo_list: bool,
+
+    /// Memo file to read instead of --input; '-' reads stdin and, unless an output is given, writes the tracked result to stdout
+    input_file: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Commands>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct CommentTag {
-    revision: String,
+impl Args {
+    // so that `cat memo.json | git-memo -` pipes memos through
+    fn apply_input_file(&mut self) {
+        if let Some(input_file) = self.input_file.take() {
+            if input_file == "-" && self.output.is_none() && self.output_dir.is_none() {
+                self.output = Some(input_file.clone());
+            }
+            self.input = Some(input_file);
+        }
+    }
+
+    // flags and environment variables win over the config file
+    fn apply_config(&mut self, config: Config) {
+        self.repo = self.repo.take().or(config.repo);
+        self.input = self.input.take().or(config.input);
+        self.output = self.output.take().or(config.output);
+        self.format = self.format.or(config.format);
+        self.abbrev = self.abbrev.or(config.abbrev);
+        // ignore globs of both are applied
+        self.ignore.extend(config.ignore);
+    }
+
+    fn input(&self) -> &str {
+        self.input.as_deref().unwrap_or("in.json")
+    }
+
+    fn output(&self) -> &str {
+        self.output.as_deref().unwrap_or("out.json")
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Commands {
+    /// Attach a new memo to a line of a file at the current revision
+    Add {
+        /// Path of the file in the repository
+        #[clap(long)]
+        file: String,
+
+        /// Line number to attach the memo to, 0 for a memo on the whole file
+        #[clap(long, required_unless_present_any = &["pattern", "hunk"])]
+        line: Option<i32>,
+
+        /// Attach the memo to the line of the file at HEAD matching this regex instead of --line
+        #[clap(long = "match", conflicts_with = "line")]
+        pattern: Option<String>,
+
+        /// Which of several lines matching --match to attach the memo to, counted from 0
+        #[clap(long, requires = "pattern")]
+        nth: Option<usize>,
+
+        /// Attach the memo to the lines of the last hunk of the uncommitted changes to the file (git diff -U0, then git diff --cached -U0) when --revision is HEAD, or else of the change --revision made to it
+        #[clap(long, conflicts_with_all = &["line", "pattern", "end-line"])]
+        hunk: bool,
+
+        /// Last line number when the memo refers to a range of lines
+        #[clap(long)]
+        end_line: Option<i32>,
+
+        /// Memo text
+        #[clap(long)]
+        text: String,
+
+        /// Revision the line numbers refer to, such as the commit checked out in another worktree
+        #[clap(long, default_value = "HEAD")]
+        revision: String,
+
+        /// Attach `key=value` metadata such as priority=high to the memo
+        #[clap(long, parse(try_from_str = parse_key_value), multiple_occurrences = true)]
+        meta: Vec<(String, String)>,
+
+        /// Also anchor the memo to the function or type defined around the line, relocating it by its offset from the definition when tracking loses the line
+        #[clap(long)]
+        symbol: bool,
+    },
+    /// Delete the memo tracked to a line at the current revision
+    Remove {
+        /// Path of the file in the repository
+        #[clap(long)]
+        file: String,
+
+        /// Line number the memo is tracked to
+        #[clap(long)]
+        line: i32,
+
+        /// Only consider memos with exactly this text
+        #[clap(long)]
+        text: Option<String>,
+
+        /// Which of several memos on the line to delete, counted from 0
+        #[clap(long)]
+        index: Option<usize>,
+    },
+    /// Drop intermediate tags, keeping the latest position of each memo
+    Gc,
+    /// Track memos to HEAD and replace the tags of each memo with its position at HEAD
+    Migrate,
+    /// Print memos mapped onto the current revision
+    List {
+        /// Only list memos of this file
+        #[clap(long)]
+        file: Option<String>,
+
+        /// Color the output: auto, always or never
+        #[clap(long, default_value = "auto")]
+        color: ColorChoice,
+
+        /// Print one `path:line:revision:status:text` record per line, with '%', ':', CR and LF percent-encoded in path and text
+        #[clap(long)]
+        porcelain: bool,
+
+        /// Print one JSON object per memo and line: {"path","line","end_line","revision","status","text"}
+        #[clap(long, conflicts_with = "porcelain")]
+        jsonl: bool,
+
+        /// Print unescaped `path\0line\0text\0` records of the memos which did not lose their line, for xargs -0
+        #[clap(short = '0', long, conflicts_with_all = &["porcelain", "jsonl"])]
+        null: bool,
+
+        /// Cut memo texts longer than this many characters, ending them with '…'
+        #[clap(long)]
+        max_width: Option<usize>,
+
+        /// Map memos onto the working tree, following uncommitted edits
+        #[clap(long)]
+        worktree: bool,
+
+        #[clap(flatten)]
+        filter: FilterArgs,
+    },
+    /// Report tag statuses and tags or files the repository no longer has, without writing
+    Doctor,
+    /// Track memos like running without a subcommand, only for files matching the globs; tracking flags may also follow `update`
+    Update {
+        /// Globs of paths from the repository root whose memos are tracked; --ignore still applies
+        pathspec: Vec<String>,
+    },
+    /// Print memos mapped onto the current revision as a jump list for editors
+    Export {
+        /// quickfix (path:line:col: text, for Vim) or vscode (JSON problems) (--format is the memo file format)
+        #[clap(long, default_value = "quickfix")]
+        export_format: ExportFormat,
+    },
+    /// Print counts of files, memos and tags of the memo file
+    Stats {
+        /// Print the counts as a JSON object
+        #[clap(long)]
+        json: bool,
+    },
+    /// Print a shell completion script: bash, zsh, fish, elvish or powershell
+    Completions { shell: Shell },
+    /// Render memos mapped onto the current revision as a document
+    Report {
+        /// Document format: markdown (--format is the memo file format)
+        #[clap(long, default_value = "markdown")]
+        report_format: ReportFormat,
+
+        #[clap(flatten)]
+        filter: FilterArgs,
+    },
+}
+
+// filters of list and report, all of which must hold
+#[derive(clap::Args, Debug, Clone)]
+struct FilterArgs {
+    /// Only memos whose metadata has this `key=value`
+    #[clap(long = "filter", parse(try_from_str = parse_key_value), multiple_occurrences = true)]
+    meta: Vec<(String, String)>,
+
+    /// Only memos whose latest tag has this status: Normal, Missing, Unknown, Fuzzy or Uncommitted
+    #[clap(long)]
+    status: Option<TagStatus>,
+
+    /// Only memos whose latest tag was stamped at this revision
+    #[clap(long)]
+    revision: Option<String>,
+
+    /// Only memos of files matching this glob from the repository root, e.g. 'src/**'
+    #[clap(long)]
+    path_glob: Option<String>,
+}
+
+impl FilterArgs {
+    fn to_memo_filter(&self, target_repo: &str) -> Result<MemoFilter> {
+        Ok(MemoFilter {
+            meta: self.meta.clone(),
+            status: self.status.clone(),
+            revision: self
+                .revision
+                .as_deref()
+                .map(|revision| git_rev_parse(target_repo, revision))
+                .transpose()?,
+            path_glob: self
+                .path_glob
+                .as_deref()
+                .map(|pattern| {
+                    Glob::new(pattern)
+                        .map(|glob| glob.compile_matcher())
+                        .with_context(|| format!("invalid glob {}", pattern))
+                })
+                .transpose()?,
+        })
+    }
+}
+
+#[derive(EnumString, Debug, PartialEq, Clone, Copy)]
+#[strum(serialize_all = "lowercase")]
+enum ReportFormat {
+    Markdown,
+}
+
+#[derive(EnumString, Debug, PartialEq, Clone, Copy)]
+#[strum(serialize_all = "lowercase")]
+enum ExportFormat {
+    Quickfix,
+    Vscode,
+}
+
+#[derive(EnumString, Debug, PartialEq, Clone, Copy)]
+#[strum(serialize_all = "lowercase")]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(&self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => is_terminal && std::env::var_os("NO_COLOR").is_none(),
+        }
+    }
+}
+
+struct ListStyle {
+    path: Style,
+    line: Style,
+    missing: Style,
+}
+
+impl ListStyle {
+    fn new(enabled: bool) -> ListStyle {
+        if enabled {
+            ListStyle {
+                path: Style::new().magenta(),
+                line: Style::new().green(),
+                missing: Style::new().red(),
+            }
+        } else {
+            ListStyle {
+                path: Style::new(),
+                line: Style::new(),
+                missing: Style::new(),
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum ListFormat {
+    Text,
+    Porcelain,
+    Jsonl,
+    Null,
+}
+
+// a line of list --jsonl
+#[derive(Serialize)]
+struct ListRecord<'a> {
+    path: &'a str,
     line: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_line: Option<i32>,
+    revision: &'a str,
     status: TagStatus,
+    text: &'a str,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct Comment {
-    text: String,
-    tags: Vec<CommentTag>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct FileData {
-    path: String,
-    comments: Vec<Comment>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-struct RootData {
-    files: Vec<FileData>,
-}
-
-#[derive(Builder, Debug, PartialEq, Clone)]
-struct GitBlameOption {
-    #[builder(setter(into))]
-    file: String,
-    #[builder(setter(into, strip_option))]
-    repo_path: Option<String>,
-    #[builder(default = "false")]
-    reverse: bool,
-    #[builder(default = "false")]
-    line_number: bool,
-    #[builder(setter(into))]
-    revision: String,
-}
-
-impl GitBlameOption {
-    fn build_command(&self) -> Command {
-        let mut command = Command::new("git");
-        if let Some(repo_path) = &self.repo_path {
-            command.arg("-C").arg(repo_path);
-        }
-        command.arg("blame");
-        if self.reverse {
-            command.arg("--reverse");
-        }
-        if self.line_number {
-            command.arg("-n");
-        }
-        command.arg(&self.revision).arg(&self.file);
-        command
-    }
-}
-fn git_current_revision(repo_path: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let child = Command::new("git")
-        .arg("-C")
-        .arg(repo_path)
-        .arg("rev-parse")
-        .arg("--short")
-        .arg("HEAD")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("git command failed to start");
-    let output = child.wait_with_output()?;
-    Ok(String::from_utf8(output.stdout).unwrap())
-}
-
-fn git_merge_base_is_ancestor(
-    repo_path: &str,
-    revision1: &str,
-    revision2: &str,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    let child = Command::new("git")
-        .arg("-C")
-        .arg(repo_path)
-        .arg("merge-base")
-        .arg("--is-ancestor")
-        .arg(revision1)
-        .arg(revision2)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("git command failed to start");
-    let output = child.wait_with_output()?;
-    Ok(output.status.code().unwrap() == 0)
-}
-
-fn git_blame_reverse(git_blame_option: &GitBlameOption) -> Result<Vec<GitBlameResult>> {
-    let child = git_blame_option
-        .build_command()
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("git command failed to start");
-
-    let mut results: Vec<GitBlameResult> = Vec::new();
-    let output = child.wait_with_output()?;
-    let exit_code = output.status.code().unwrap();
-    if exit_code != 0 {
-        let stderr_output = String::from_utf8(output.stderr).unwrap();
-        return Err(anyhow!(
-            "Failed to run git command: exit_code={}, stderr={}",
-            exit_code,
-            stderr_output
-        ));
-    }
-    let stdout_output = String::from_utf8(output.stdout).unwrap();
-    for line in stdout_output.lines() {
-        // TODO: add test code
-        let result = GitBlameResult::new_from_line(
-            r"^(?P<revision>[^ ]+) (?P<new_line>[^ ]+) [^)]+ (?P<orig_line>[0-9]+)\)",
-            line,
+#[allow(clippy::too_many_arguments)]
+fn list_memos(
+    target_repo: &str,
+    data: &RootData,
+    file: Option<&str>,
+    color: ColorChoice,
+    list_format: ListFormat,
+    worktree: bool,
+    max_width: Option<usize>,
+    // print memos which lost their lines instead of warning about them
+    missing: bool,
+) -> Result<()> {
+    let style = ListStyle::new(color.enabled(std::io::stdout().is_terminal()));
+    let stderr_style = ListStyle::new(color.enabled(std::io::stderr().is_terminal()));
+    let mut resolver = TagResolver::default();
+    let mut blame_cache: HashMap<String, Vec<GitBlameResult>> = HashMap::new();
+    for file_data in &data.files {
+        if file.is_some_and(|file| {
+            normalize_path(target_repo, file) != normalize_path(target_repo, &file_data.path)
+        }) {
+            continue;
+        }
+        let repo = file_data.repo_path(target_repo);
+        for comment in &file_data.comments {
+            if !missing
+                && comment
+                    .tags
+                    .iter()
+                    .all(|tag| tag.status == TagStatus::Missing)
+            {
+                continue;
+            }
+            if let Some(tag) = resolver.resolve(&repo, comment)? {
+                let tag = if worktree && tag.status.is_anchored() {
+                    match locate_in_worktree(&repo, &file_data.path, tag, &mut blame_cache)? {
+                        Some(tag) => tag,
+                        None => CommentTag {
+                            status: TagStatus::Missing,
+                            ..tag.clone()
+                        },
+                    }
+                } else {
+                    tag.clone()
+                };
+                if list_format == ListFormat::Jsonl {
+                    let record = ListRecord {
+                        path: &file_data.path,
+                        line: tag.line,
+                        end_line: tag.end_line,
+                        revision: &tag.revision,
+                        status: tag.status,
+                        text: &comment.text,
+                    };
+                    println!("{}", serde_json::to_string(&record)?);
+                    continue;
+                }
+                if list_format == ListFormat::Porcelain {
+                    println!(
+                        "{}:{}:{}:{}:{}",
+                        percent_encode(&file_data.path),
+                        tag.line_span(),
+                        tag.revision,
+                        tag.status,
+                        percent_encode(&comment.text)
+                    );
+                    continue;
+                }
+                if tag.status == TagStatus::Missing && !missing {
+                    warning!(
+                        "{}",
+                        format!(
+                            "warning: memo lost its anchor: {}:{}: {}",
+                            file_data.path,
+                            tag.line_span(),
+                            comment.text
+                        )
+                        .style(stderr_style.missing)
+                    );
+                    continue;
+                }
+                if list_format == ListFormat::Null {
+                    print!(
+                        "{}\0{}\0{}\0",
+                        file_data.path,
+                        tag.line_span(),
+                        comment.text
+                    );
+                    continue;
+                }
+                let text = match max_width {
+                    Some(max_width) => truncate_text(&comment.text, max_width),
+                    None => comment.text.clone(),
+                };
+                let marker = if tag.status == TagStatus::Missing {
+                    format!(" {}", "(Missing)".style(style.missing))
+                } else {
+                    String::new()
+                };
+                if tag.is_file_level() {
+                    println!(
+                        "{}: {}{}{}",
+                        file_data.path.style(style.path),
+                        text,
+                        marker,
+                        last_change(&tag)
+                    );
+                    continue;
+                }
+                println!(
+                    "{}:{}: {}{}{}",
+                    file_data.path.style(style.path),
+                    tag.line_span().style(style.line),
+                    text,
+                    marker,
+                    last_change(&tag)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// cut on a character boundary, since slicing bytes panics inside multibyte characters
+fn truncate_text(text: &str, max_width: usize) -> String {
+    match text.char_indices().nth(max_width) {
+        Some(_) => {
+            // the ellipsis takes the place of the last character
+            let end = text
+                .char_indices()
+                .nth(max_width.saturating_sub(1))
+                .map_or(0, |(index, _)| index);
+            format!("{}…", &text[..end])
+        }
+        None => text.to_string(),
+    }
+}
+
+// key=value of --meta and --filter
+fn parse_key_value(pair: &str) -> Result<(String, String)> {
+    match pair.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(anyhow!("expected key=value: {}", pair)),
+    }
+}
+
+// keeps every porcelain record on one line with a fixed number of fields
+fn percent_encode(field: &str) -> String {
+    let mut encoded = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '%' | ':' | '\r' | '\n' => encoded.push_str(&format!("%{:02X}", c as u32)),
+            _ => encoded.push(c),
+        }
+    }
+    encoded
+}
+
+// " (last modified by X on YYYY-MM-DD)" when recorded with --record-author
+fn last_change(tag: &CommentTag) -> String {
+    match (&tag.author, &tag.committed_at) {
+        (Some(author), Some(committed_at)) => format!(
+            " (last modified by {} on {})",
+            author,
+            committed_at.split(' ').next().unwrap_or(committed_at)
+        ),
+        (Some(author), None) => format!(" (last modified by {})", author),
+        _ => String::new(),
+    }
+}
+
+// a section per file with a `path#Lnn` anchor per memo
+fn report_markdown(target_repo: &str, data: &RootData) -> Result<String> {
+    let mut resolver = TagResolver::default();
+    let mut report = String::new();
+    for file_data in &data.files {
+        let repo = file_data.repo_path(target_repo);
+        let mut items = Vec::new();
+        for comment in &file_data.comments {
+            if let Some(tag) = resolver.resolve(&repo, comment)? {
+                let (span, anchor) = match tag.end_line {
+                    _ if tag.is_file_level() => (file_data.path.clone(), file_data.path.clone()),
+                    Some(end_line) => (
+                        tag.line_span(),
+                        format!("{}#L{}-L{}", file_data.path, tag.line, end_line),
+                    ),
+                    None => (tag.line_span(), format!("{}#L{}", file_data.path, tag.line)),
+                };
+                let warning = if tag.status == TagStatus::Missing {
+                    "⚠️ "
+                } else {
+                    ""
+                };
+                items.push(format!(
+                    "- {}[{}]({}): {}{}\n",
+                    warning,
+                    span,
+                    anchor,
+                    comment.text.replace('\n', " "),
+                    last_change(tag)
+                ));
+            }
+        }
+        if items.is_empty() {
+            continue;
+        }
+        if !report.is_empty() {
+            report.push('\n');
+        }
+        report.push_str(&format!("## {}\n\n", file_data.path));
+        for item in items {
+            report.push_str(&item);
+        }
+    }
+    Ok(report)
+}
+
+// a problem of export --export-format vscode
+#[derive(Serialize)]
+struct VscodeProblem<'a> {
+    file: &'a str,
+    line: i32,
+    column: i32,
+    #[serde(rename = "endLine", skip_serializing_if = "Option::is_none")]
+    end_line: Option<i32>,
+    severity: &'a str,
+    message: String,
+}
+
+// (path, tag, text) of each memo mapped onto HEAD
+fn resolved_memos<'a>(
+    target_repo: &str,
+    data: &'a RootData,
+) -> Result<Vec<(&'a str, &'a CommentTag, &'a str)>> {
+    let mut resolver = TagResolver::default();
+    let mut memos = Vec::new();
+    for file_data in &data.files {
+        let repo = file_data.repo_path(target_repo);
+        for comment in &file_data.comments {
+            if let Some(tag) = resolver.resolve(&repo, comment)? {
+                memos.push((file_data.path.as_str(), tag, comment.text.as_str()));
+            }
+        }
+    }
+    Ok(memos)
+}
+
+fn export_memos(target_repo: &str, data: &RootData, export_format: ExportFormat) -> Result<String> {
+    let memos = resolved_memos(target_repo, data)?;
+    // the jump list holds one line per memo
+    let message = |tag: &CommentTag, text: &str| {
+        let text = text.replace('\n', " ");
+        if tag.status == TagStatus::Missing {
+            format!("(Missing) {}", text)
+        } else {
+            text
+        }
+    };
+    match export_format {
+        ExportFormat::Quickfix => Ok(memos
+            .iter()
+            .map(|(path, tag, text)| {
+                format!("{}:{}:1: {}\n", path, tag.line.max(1), message(tag, text))
+            })
+            .collect()),
+        ExportFormat::Vscode => {
+            let problems: Vec<VscodeProblem> = memos
+                .iter()
+                .map(|(path, tag, text)| VscodeProblem {
+                    file: path,
+                    // editors have no line 0 for memos on the whole file
+                    line: tag.line.max(1),
+                    column: 1,
+                    end_line: tag.end_line,
+                    severity: if tag.status == TagStatus::Missing {
+                        "warning"
+                    } else {
+                        "info"
+                    },
+                    message: message(tag, text),
+                })
+                .collect();
+            Ok(format!("{}\n", serde_json::to_string_pretty(&problems)?))
+        }
+    }
+}
+
+// the store --backend selects for the memo file at path
+fn memo_store(args: &Args, target_repo: &str, path: &str) -> Box<dyn MemoStore> {
+    match args.backend {
+        MemoBackend::Json if Path::new(path).is_dir() => Box::new(DirStore {
+            dir: path.to_string(),
+            format: args.format,
+        }),
+        MemoBackend::Json => Box::new(JsonFileStore {
+            path: path.to_string(),
+            format: args.format,
+            backup: args.backup,
+        }),
+        MemoBackend::GitNotes => Box::new(GitNotesStore {
+            repo_path: target_repo.to_string(),
+            runner: &SystemGit,
+        }),
+    }
+}
+
+fn load_memos(args: &Args, target_repo: &str) -> Result<RootData> {
+    memo_store(args, target_repo, args.input()).load()
+}
+
+// memo files of --input-glob with the file each merged comment came from
+struct MergedInputs {
+    paths: Vec<String>,
+    origins: Vec<Vec<usize>>,
+}
+
+fn load_merged_memos(
+    args: &Args,
+    target_repo: &str,
+    pattern: &str,
+) -> Result<(RootData, MergedInputs)> {
+    let mut paths = Vec::new();
+    // matches are in alphabetical order, which is the order of merging
+    for entry in glob::glob(pattern).with_context(|| format!("invalid --input-glob {}", pattern))? {
+        paths.push(entry?.to_string_lossy().to_string());
+    }
+    if paths.is_empty() {
+        return Err(anyhow!("no memo file matches --input-glob {}", pattern));
+    }
+    let parts = paths
+        .iter()
+        .map(|path| memo_store(args, target_repo, path).load())
+        .collect::<Result<Vec<_>>>()?;
+    let (data, origins) = merge_root_data(parts);
+    Ok((data, MergedInputs { paths, origins }))
+}
+
+fn save_memos(args: &Args, target_repo: &str, output: &str, data: &mut RootData) -> Result<()> {
+    if args.sort {
+        sort_memos(data);
+    }
+    memo_store(args, target_repo, output).save(data)
+}
+
+fn update_option(args: &Args, to: String, include: Vec<String>) -> Result<UpdateOption> {
+    Ok(UpdateOptionBuilder::default()
+        .from(args.from.clone())
+        .to(to)
+        .abbrev(args.abbrev)
+        .revision_format(args.revision_format)
+        .jobs(args.jobs)
+        .detect_moves(args.detect_moves)
+        .detect_copies(args.detect_copies)
+        .ignore_whitespace(args.ignore_whitespace)
+        .first_parent(args.first_parent)
+        .ancestry_path(args.ancestry_path)
+        .backward(args.backward)
+        .follow(args.follow)
+        .fuzz(args.fuzz)
+        .since(args.since.clone())
+        .record_author(args.record_author)
+        .ignore(args.ignore.clone())
+        .include(include)
+        .verbose(args.verbose)
+        // step logs would tear the bar apart
+        .progress(!args.quiet && !args.verbose && std::io::stderr().is_terminal())
+        .build()?)
+}
+
+fn track_memos(
+    args: &Args,
+    target_repo: &str,
+    data: RootData,
+    merged_inputs: Option<&MergedInputs>,
+) -> Result<i32> {
+    let pathspec = match &args.command {
+        Some(Commands::Update { pathspec }) => pathspec.clone(),
+        _ => Vec::new(),
+    };
+    if args.verbose {
+        eprintln!("[input data]");
+        eprintln!("{}", serde_json::to_string_pretty(&data)?);
+    }
+
+    // the input is only kept around when it is compared with the result
+    let old_data = if args.dry_run || args.check || args.fail_on_missing || args.diff {
+        Some(data.clone())
+    } else {
+        None
+    };
+    let to = if args.check {
+        git_commit_index(target_repo)?
+    } else {
+        args.to.clone()
+    };
+    // easy to do by accident, e.g. in the middle of a bisect
+    if to == "HEAD" && git_is_detached_head(target_repo)? {
+        warning!(
+            "note: HEAD is detached at {}, so memos are tracked to that commit rather than a branch",
+            git_current_revision(target_repo, args.revision_format, args.abbrev)?
         );
-        results.push(result);
-    }
-    Ok(results)
-}
-
-#[derive(Debug, PartialEq)]
-struct GitBlameResult {
-    revision: String,
-    orig_line_number: i32,
-    new_line_number: i32,
-}
-impl GitBlameResult {
-    fn new_from_line(pattern: &str, line: &str) -> GitBlameResult {
-        let re = Regex::new(pattern).unwrap();
-        let captures = re.captures(line).unwrap();
-
-        let revision = captures.name("revision").unwrap().as_str();
-        let new_line_number = captures
-            .name("new_line")
-            .unwrap()
-            .as_str()
-            .parse::<i32>()
-            .unwrap();
-        let orig_line_number = captures
-            .name("orig_line")
-            .unwrap()
-            .as_str()
-            .parse::<i32>()
-            .unwrap();
-        return GitBlameResult {
-            new_line_number: new_line_number,
-            orig_line_number: orig_line_number,
-            revision: revision.to_string(),
+    }
+    let mut new_data = update_memos(target_repo, data, &update_option(args, to, pathspec)?)?;
+
+    if args.verbose {
+        eprintln!("[output data]");
+        eprintln!("{}", serde_json::to_string_pretty(&new_data)?);
+    }
+
+    if let (true, Some(old_data)) = (args.diff, &old_data) {
+        eprint!("{}", diff_update(old_data, &new_data));
+    }
+    if let (true, Some(old_data)) = (args.check, &old_data) {
+        let lost = lost_memos(old_data, &new_data);
+        for (path, comment, tag) in &lost {
+            eprintln!("at risk: {}:{}: {}", path, tag.line_span(), comment.text);
+        }
+        return Ok(if lost.is_empty() { 0 } else { 1 });
+    }
+    if let (true, Some(old_data)) = (args.dry_run, &old_data) {
+        let summary = summarize_update(old_data, &new_data);
+        eprintln!("{}", summary);
+        return Ok(if summary.has_changes() { 1 } else { 0 });
+    }
+    // --sort reorders new_data, so the lost memos are found before writing
+    let lost: Vec<String> = match (args.fail_on_missing, &old_data) {
+        (true, Some(old_data)) => lost_memos(old_data, &new_data)
+            .iter()
+            .map(|(path, comment, tag)| format!("{}:{}: {}", path, tag.line_span(), comment.text))
+            .collect(),
+        _ => Vec::new(),
+    };
+    match (merged_inputs, &args.output) {
+        (Some(merged_inputs), None) if args.output_dir.is_none() => {
+            let parts =
+                split_root_data(new_data, &merged_inputs.origins, merged_inputs.paths.len())?;
+            for (path, mut part) in merged_inputs.paths.iter().zip(parts) {
+                save_memos(args, target_repo, path, &mut part)?;
+            }
+        }
+        _ => match &args.output_dir {
+            Some(output_dir) => {
+                std::fs::create_dir_all(output_dir)
+                    .with_context(|| format!("failed to create {}", output_dir))?;
+                save_memos(args, target_repo, output_dir, &mut new_data)?
+            }
+            None => save_memos(args, target_repo, args.output(), &mut new_data)?,
+        },
+    }
+    if !lost.is_empty() {
+        for memo in &lost {
+            eprintln!("missing: {}", memo);
+        }
+        return Ok(2);
+    }
+    Ok(0)
+}
+
+// root of the target repository, after applying its config to args
+fn resolve_repo(args: &mut Args) -> Result<String> {
+    let repo_root = match &args.repo {
+        Some(repo) => git_show_toplevel(repo)?,
+        None => git_show_toplevel(&std::env::current_dir()?.to_string_lossy())?,
+    };
+    args.apply_config(Config::load(&repo_root)?);
+    // memo paths are resolved from the root even when --repo is a subdirectory
+    match &args.repo {
+        Some(repo) => git_show_toplevel(repo),
+        None => Ok(repo_root),
+    }
+}
+
+// tracks the memo file of each `repo_path<TAB>memo_file` line of stdin in place
+fn track_repo_list(args: &Args) -> Result<i32> {
+    let mut failed = Vec::new();
+    let mut count = 0;
+    for line in std::io::stdin().lines() {
+        let line = line.context("failed to read the repository list from stdin")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        count += 1;
+        let result = match line.split_once('\t') {
+            Some((repo, memo_file)) => {
+                let mut repo_args = args.clone();
+                repo_args.repo = Some(repo.to_string());
+                repo_args.input = Some(memo_file.to_string());
+                repo_args.output = Some(memo_file.to_string());
+                resolve_repo(&mut repo_args).and_then(|target_repo| {
+                    let data = load_memos(&repo_args, &target_repo)?;
+                    track_memos(&repo_args, &target_repo, data, None)
+                })
+            }
+            None => Err(anyhow!("expected repo_path<TAB>memo_file")),
         };
+        match result {
+            Ok(0) => eprintln!("ok: {}", line),
+            Ok(exit_code) => {
+                eprintln!("failed: {}: exit code {}", line, exit_code);
+                failed.push(line);
+            }
+            Err(err) => {
+                eprintln!("failed: {}: {:#}", line, err);
+                failed.push(line);
+            }
+        }
     }
+    eprintln!(
+        "{} of {} repositories tracked, {} failed",
+        count - failed.len(),
+        count,
+        failed.len()
+    );
+    Ok(if failed.is_empty() { 0 } else { 1 })
 }
 
-#[allow(dead_code)]
-fn get_sample_data() -> RootData {
-    let data = RootData {
-        files: vec![FileData {
-            path: String::from("./README.md"),
-            comments: vec![
-                Comment {
-                    text: String::from("hello A"),
-                    tags: vec![CommentTag {
-                        revision: String::from("39690ed"),
-                        line: 1,
-                        status: TagStatus::Normal,
-                    }],
-                },
-                Comment {
-                    text: String::from("hello B"),
-                    tags: vec![CommentTag {
-                        revision: String::from("39690ed"),
-                        line: 2,
-                        status: TagStatus::Normal,
-                    }],
-                },
-            ],
-        }],
+fn run() -> Result<i32> {
+    let mut args = Args::parse();
+    args.apply_input_file();
+    set_quiet(args.quiet);
+    // needs neither a repository nor memos
+    if let Some(Commands::Completions { shell }) = args.command {
+        let mut command = Args::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+        return Ok(0);
+    }
+    configure_git(GitSettings {
+        binary: args.git_binary.clone(),
+        timeout: args.timeout.map(Duration::from_secs),
+        retries: args.retries,
+    });
+    if args.stdin_repo_list {
+        if !matches!(args.command, None | Some(Commands::Update { .. })) {
+            return Err(anyhow!("--stdin-repo-list only tracks memos"));
+        }
+        return track_repo_list(&args);
+    }
+    let target_repo = resolve_repo(&mut args)?;
+    let target_repo = target_repo.as_str();
+    if args.input_glob.is_some() {
+        if matches!(args.backend, MemoBackend::GitNotes) {
+            return Err(anyhow!("--input-glob reads memo files, not git notes"));
+        }
+        // these write back to --input, which is ambiguous for many files
+        if matches!(
+            args.command,
+            Some(Commands::Add { .. } | Commands::Remove { .. } | Commands::Gc | Commands::Migrate)
+        ) {
+            return Err(anyhow!(
+                "--input-glob is only supported when tracking and by read-only subcommands"
+            ));
+        }
+    }
+    let (mut data, merged_inputs) = match &args.input_glob {
+        Some(pattern) => {
+            let (data, merged_inputs) = load_merged_memos(&args, target_repo, pattern)?;
+            (data, Some(merged_inputs))
+        }
+        None => (load_memos(&args, target_repo)?, None),
     };
-    data
-}
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let target_repo = "./example-repo";
-    let file = File::open("in.json").unwrap();
-    let reader = BufReader::new(file);
-    let data: RootData = serde_json::from_reader(reader).unwrap();
-
-    let mut new_data = data.clone();
-    let current_revision = git_current_revision(target_repo).unwrap();
-    for (file_index, file_data) in data.files.iter().enumerate() {
-        println!("file path = {}", file_data.path);
-        for (comment_index, comment) in file_data.comments.iter().enumerate() {
-            println!("comment text = {}", comment.text);
-            for tag in &comment.tags {
-                println!("comment text = {:?}", tag);
-                if tag.revision == current_revision {
-                    println!("same revision skip: {:?}", tag);
-                    continue;
+
+    match &args.command {
+        Some(Commands::Add {
+            file,
+            line,
+            pattern,
+            nth,
+            hunk,
+            end_line,
+            text,
+            revision,
+            meta,
+            symbol,
+        }) => {
+            // the tag is stamped with the commit itself so that tracking starts from it
+            let revision =
+                git_commit_revision(target_repo, revision, args.revision_format, args.abbrev)?;
+            let (revision, line, end_line) = match (line, pattern, hunk) {
+                (Some(line), _, _) => (revision, *line, *end_line),
+                (None, Some(pattern), _) => {
+                    let line = find_line(target_repo, &revision, file, pattern, *nth)?;
+                    (revision, line, *end_line)
                 }
-                let is_ancestor =
-                    git_merge_base_is_ancestor(target_repo, &tag.revision, "HEAD").unwrap();
-                println!("is_ancestor = {:?}", is_ancestor);
-                if is_ancestor {
-                    // if use -L option, there is no need to parse entire file lines
-                    // if no use -L option and cache the result, we can reuse the result
-                    let git_blame_option = GitBlameOptionBuilder::default()
-                        .file(&file_data.path)
-                        .repo_path(target_repo)
-                        .reverse(true)
-                        .line_number(true)
-                        .revision(tag.revision.clone() + "..HEAD")
-                        .build()
-                        .unwrap();
-                    let results = git_blame_reverse(&git_blame_option).unwrap();
-                    if 1 <= tag.line && tag.line <= results.len() as i32 {
-                        let new_info = &results[(tag.line - 1) as usize];
-                        // for debug
-                        // println!(
-                        //     "new revision:{}, new line number:{}",
-                        //     new_info.revision, new_info.new_line_number
-                        // );
-                        new_data.files[file_index].comments[comment_index]
-                            .tags
-                            .push(CommentTag {
-                                revision: String::from(&new_info.revision),
-                                line: new_info.new_line_number,
-                                status: TagStatus::Normal,
-                            });
-                    }
-                    for result in results {
-                        println!("{:?}", result);
-                    }
+                // the lines of an uncommitted hunk are stamped with the all-zero revision
+                (None, None, true) => {
+                    let (revision, start, end) = find_hunk(target_repo, &revision, file)?;
+                    (revision, start, (end > start).then_some(end))
                 }
+                (None, None, false) => unreachable!("clap requires --line, --match or --hunk"),
+            };
+            add_memo(
+                target_repo,
+                &mut data,
+                file,
+                line,
+                end_line,
+                text,
+                &revision,
+                meta.iter().cloned().collect(),
+                *symbol,
+            )?;
+            save_memos(&args, target_repo, args.input(), &mut data)?;
+        }
+        Some(Commands::Remove {
+            file,
+            line,
+            text,
+            index,
+        }) => {
+            let comment =
+                remove_memo(target_repo, &mut data, file, *line, text.as_deref(), *index)?;
+            warning!("removed: {}:{}: {}", file, line, comment.text);
+            save_memos(&args, target_repo, args.input(), &mut data)?;
+        }
+        Some(Commands::Gc) => {
+            gc_memos(&mut data);
+            save_memos(&args, target_repo, args.input(), &mut data)?;
+        }
+        Some(Commands::Migrate) => {
+            let mut new_data = update_memos(
+                target_repo,
+                data,
+                &update_option(&args, "HEAD".to_string(), Vec::new())?,
+            )?;
+            for (path, comment) in migrate_memos(target_repo, &mut new_data)? {
+                warning!("warning: memo lost its anchor: {}: {}", path, comment.text);
             }
+            save_memos(&args, target_repo, args.input(), &mut new_data)?;
+        }
+        Some(Commands::List {
+            file,
+            color,
+            porcelain,
+            jsonl,
+            null,
+            max_width,
+            worktree,
+            filter,
+        }) => {
+            let list_format = if *null {
+                ListFormat::Null
+            } else if *jsonl {
+                ListFormat::Jsonl
+            } else if *porcelain {
+                ListFormat::Porcelain
+            } else {
+                ListFormat::Text
+            };
+            list_memos(
+                target_repo,
+                &filter_memos(target_repo, &data, &filter.to_memo_filter(target_repo)?),
+                file.as_deref(),
+                *color,
+                list_format,
+                *worktree,
+                *max_width,
+                filter.status == Some(TagStatus::Missing),
+            )?;
+        }
+        Some(Commands::Doctor) => {
+            let report = diagnose_memos(target_repo, &data)?;
+            print!("{}", report);
+            return Ok(if report.has_problems() { 1 } else { 0 });
+        }
+        Some(Commands::Export { export_format }) => {
+            print!("{}", export_memos(target_repo, &data, *export_format)?)
+        }
+        Some(Commands::Stats { json }) => {
+            let stats = memo_stats(target_repo, &data)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                print!("{}", stats);
+            }
+        }
+        Some(Commands::Report {
+            report_format,
+            filter,
+        }) => {
+            let data = filter_memos(target_repo, &data, &filter.to_memo_filter(target_repo)?);
+            match report_format {
+                ReportFormat::Markdown => print!("{}", report_markdown(target_repo, &data)?),
+            }
+        }
+        Some(Commands::Completions { .. }) => unreachable!("handled before loading memos"),
+        None | Some(Commands::Update { .. }) => {
+            return track_memos(&args, target_repo, data, merged_inputs.as_ref())
         }
     }
+    Ok(0)
+}
 
-    let serialized = serde_json::to_string_pretty(&data)?;
-    println!("[input data]");
-    println!("{}", serialized);
-
-    let new_serialized = serde_json::to_string_pretty(&new_data)?;
-    println!("[output data]");
-    println!("{}", new_serialized);
-
-    let mut outfile = File::create("out.json")?;
-    outfile.write_all(new_serialized.as_bytes())?;
-    Ok(())
+fn main() {
+    match run() {
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            std::process::exit(1);
+        }
+    }
 }
@@ -0,0 +1,172 @@
+use crate::runner::{GitOutput, GitRunner};
+use crate::store::MemoStore;
+use crate::{
+    git_rev_parse_long_with, is_zero_revision, migrate_root_data, validate_root_data, Comment,
+    FileData, RootData, TagStatus, ROOT_DATA_VERSION,
+};
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+
+// memos are kept apart from the default refs/notes/commits
+const NOTES_REF: &str = "git-memo";
+
+// a note body holds every comment anchored to the commit
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct NoteEntry {
+    path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repo: Option<String>,
+    comment: Comment,
+}
+
+fn git_notes(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    args: &[&str],
+    stdin: Option<&str>,
+) -> Result<GitOutput> {
+    let mut git_args = vec![
+        String::from("-C"),
+        repo_path.to_string(),
+        String::from("notes"),
+        String::from("--ref"),
+        String::from(NOTES_REF),
+    ];
+    git_args.extend(args.iter().map(|arg| arg.to_string()));
+    let output = match stdin {
+        Some(stdin) => runner.run_with_input(&git_args, "git notes", stdin.as_bytes())?,
+        None => runner.run(&git_args, "git notes")?,
+    };
+    if !output.success() {
+        return Err(anyhow!(
+            "Failed to run git notes {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output)
+}
+
+// "<note object> <annotated commit>" per line
+fn list_noted_commits(runner: &dyn GitRunner, repo_path: &str) -> Result<Vec<String>> {
+    let output = git_notes(runner, repo_path, &["list"], None)?;
+    let stdout_output =
+        String::from_utf8(output.stdout).context("git notes output is not valid UTF-8")?;
+    Ok(stdout_output
+        .lines()
+        .filter_map(|line| line.split(' ').nth(1))
+        .map(String::from)
+        .collect())
+}
+
+pub fn load_root_data(runner: &dyn GitRunner, repo_path: &str) -> Result<RootData> {
+    let mut data = RootData {
+        version: ROOT_DATA_VERSION,
+        files: Vec::new(),
+    };
+    for commit in list_noted_commits(runner, repo_path)? {
+        let output = git_notes(runner, repo_path, &["show", &commit], None)?;
+        let entries: Vec<NoteEntry> = serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("invalid git-memo note on {}", commit))?;
+        for entry in entries {
+            match data
+                .files
+                .iter_mut()
+                .find(|file_data| file_data.path == entry.path && file_data.repo == entry.repo)
+            {
+                Some(file_data) => file_data.comments.push(entry.comment),
+                None => data.files.push(FileData {
+                    path: entry.path,
+                    repo: entry.repo,
+                    comments: vec![entry.comment],
+                }),
+            }
+        }
+    }
+    let data = migrate_root_data(data).context("invalid git-memo notes")?;
+    validate_root_data(&data).context("invalid git-memo notes")?;
+    Ok(data)
+}
+
+// commit the note of a comment is attached to: the revision of the latest tag
+// which is a commit of the repository, or HEAD when there is none (the
+// revisions of memos of sub-repositories, Unknown tags and lines which were
+// not committed yet), so that the memo is kept rather than failing the save
+fn note_commit(
+    runner: &dyn GitRunner,
+    repo_path: &str,
+    file_data: &FileData,
+    comment: &Comment,
+) -> Result<String> {
+    if file_data.repo.is_none() {
+        let commit = comment
+            .tags
+            .iter()
+            .rev()
+            .filter(|tag| tag.status != TagStatus::Unknown && !is_zero_revision(&tag.revision))
+            .find_map(|tag| git_rev_parse_long_with(runner, repo_path, &tag.revision).ok());
+        if let Some(commit) = commit {
+            return Ok(commit);
+        }
+        warning!(
+            "warning: no tag of the memo is a commit of the repository, so its note is attached to HEAD: {}: {}",
+            file_data.path,
+            comment.text
+        );
+    }
+    git_rev_parse_long_with(runner, repo_path, "HEAD")
+}
+
+// the note of each commit holds the comments attached to it by note_commit
+pub fn save_root_data(runner: &dyn GitRunner, repo_path: &str, data: &RootData) -> Result<()> {
+    let mut notes: BTreeMap<String, Vec<NoteEntry>> = BTreeMap::new();
+    for file_data in &data.files {
+        for comment in &file_data.comments {
+            if comment.tags.is_empty() {
+                return Err(anyhow!(
+                    "memo without tags in {}: {}",
+                    file_data.path,
+                    comment.text
+                ));
+            }
+            let commit = note_commit(runner, repo_path, file_data, comment)?;
+            notes.entry(commit).or_default().push(NoteEntry {
+                path: file_data.path.clone(),
+                repo: file_data.repo.clone(),
+                comment: comment.clone(),
+            });
+        }
+    }
+    for commit in list_noted_commits(runner, repo_path)? {
+        if !notes.contains_key(&commit) {
+            git_notes(runner, repo_path, &["remove", &commit], None)?;
+        }
+    }
+    for (commit, entries) in &notes {
+        let body = serde_json::to_string_pretty(entries)?;
+        git_notes(
+            runner,
+            repo_path,
+            &["add", "-f", "-F", "-", commit],
+            Some(&body),
+        )?;
+    }
+    Ok(())
+}
+
+// refs/notes/git-memo of the repository, read and written by runner
+#[derive(Clone)]
+pub struct GitNotesStore<'a> {
+    pub repo_path: String,
+    pub runner: &'a dyn GitRunner,
+}
+
+impl MemoStore for GitNotesStore<'_> {
+    fn load(&self) -> Result<RootData> {
+        load_root_data(self.runner, &self.repo_path)
+    }
+
+    fn save(&self, data: &RootData) -> Result<()> {
+        save_root_data(self.runner, &self.repo_path, data)
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{git_command, retry_git, stream_git};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::process::Stdio;
+use std::sync::Mutex;
+
+// what a git command left behind; exit_code is None when it was killed by a signal
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct GitOutput {
+    pub exit_code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl GitOutput {
+    pub fn new(exit_code: i32, stdout: &str, stderr: &str) -> GitOutput {
+        GitOutput {
+            exit_code: Some(exit_code),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+// runs git with the arguments following the binary, so that the functions
+// taking a runner can be driven by canned output without git
+pub trait GitRunner: Sync {
+    // stdout is handed to consume as it is written, which may happen again
+    // when the command is retried; returns the exit code and stderr
+    fn stream(
+        &self,
+        args: &[String],
+        name: &str,
+        consume: &mut dyn FnMut(&mut dyn BufRead) -> Result<()>,
+    ) -> Result<(Option<i32>, Vec<u8>)>;
+
+    // like run, with input written to the stdin of git
+    fn run_with_input(&self, args: &[String], name: &str, input: &[u8]) -> Result<GitOutput>;
+
+    fn run(&self, args: &[String], name: &str) -> Result<GitOutput> {
+        let mut stdout = Vec::new();
+        let (exit_code, stderr) = self.stream(args, name, &mut |reader| {
+            stdout.clear();
+            reader.read_to_end(&mut stdout)?;
+            Ok(())
+        })?;
+        Ok(GitOutput {
+            exit_code,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+// the configured git binary, with the retries and the timeout of GitSettings
+pub struct SystemGit;
+
+impl GitRunner for SystemGit {
+    fn stream(
+        &self,
+        args: &[String],
+        name: &str,
+        consume: &mut dyn FnMut(&mut dyn BufRead) -> Result<()>,
+    ) -> Result<(Option<i32>, Vec<u8>)> {
+        let mut command = git_command();
+        command
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let (status, stderr, ()) =
+            retry_git(|| stream_git(&mut command, name, None, |reader| consume(reader)))?;
+        Ok((status.code(), stderr))
+    }
+
+    fn run_with_input(&self, args: &[String], name: &str, input: &[u8]) -> Result<GitOutput> {
+        let mut command = git_command();
+        command
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let (status, stderr, stdout) = retry_git(|| {
+            stream_git(&mut command, name, Some(input), |reader| {
+                let mut stdout = Vec::new();
+                reader.read_to_end(&mut stdout)?;
+                Ok(stdout)
+            })
+        })?;
+        Ok(GitOutput {
+            exit_code: status.code(),
+            stdout,
+            stderr,
+        })
+    }
+}
+
+// answers each argument vector with its canned output and records the
+// commands run and their input; any other command fails
+#[derive(Debug, Default)]
+pub struct FakeGit {
+    responses: HashMap<Vec<String>, GitOutput>,
+    calls: Mutex<Vec<Vec<String>>>,
+    inputs: Mutex<Vec<(Vec<String>, Vec<u8>)>>,
+}
+
+impl FakeGit {
+    pub fn respond(mut self, args: &[&str], output: GitOutput) -> FakeGit {
+        self.responses
+            .insert(args.iter().map(|arg| arg.to_string()).collect(), output);
+        self
+    }
+
+    // argument vectors of the commands run so far, in order
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    // argument vectors and input of the commands run with input so far, in order
+    pub fn inputs(&self) -> Vec<(Vec<String>, Vec<u8>)> {
+        self.inputs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+}
+
+impl GitRunner for FakeGit {
+    fn stream(
+        &self,
+        args: &[String],
+        name: &str,
+        consume: &mut dyn FnMut(&mut dyn BufRead) -> Result<()>,
+    ) -> Result<(Option<i32>, Vec<u8>)> {
+        self.calls
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(args.to_vec());
+        let output = self
+            .responses
+            .get(args)
+            .with_context(|| format!("no canned output for {}: git {}", name, args.join(" ")))?;
+        consume(&mut output.stdout.as_slice())?;
+        Ok((output.exit_code, output.stderr.clone()))
+    }
+
+    fn run_with_input(&self, args: &[String], name: &str, input: &[u8]) -> Result<GitOutput> {
+        self.inputs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push((args.to_vec(), input.to_vec()));
+        self.run(args, name)
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{load_root_data, merge_root_data, save_root_data, FileData, MemoFormat, RootData};
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+// where memos are read from and written to, independent of tracking
+pub trait MemoStore {
+    fn load(&self) -> Result<RootData>;
+    fn save(&self, data: &RootData) -> Result<()>;
+}
+
+// a json or yaml memo file, '-' for stdin and stdout
+#[derive(Debug, Clone)]
+pub struct JsonFileStore {
+    pub path: String,
+    // guessed from the file extension when None
+    pub format: Option<MemoFormat>,
+    // the file being overwritten is copied to <path>.bak first
+    pub backup: bool,
+}
+
+impl MemoStore for JsonFileStore {
+    fn load(&self) -> Result<RootData> {
+        load_root_data(&self.path, self.format)
+    }
+
+    fn save(&self, data: &RootData) -> Result<()> {
+        if self.backup && self.path != "-" && Path::new(&self.path).exists() {
+            let backup = format!("{}.bak", self.path);
+            std::fs::copy(&self.path, &backup)
+                .with_context(|| format!("failed to back up {} to {}", self.path, backup))?;
+        }
+        save_root_data(&self.path, data, self.format)
+    }
+}
+
+// one memo file per annotated file under dir, named after the path of the file
+// so that diffs of the directory stay local to the annotated files
+#[derive(Debug, Clone)]
+pub struct DirStore {
+    pub dir: String,
+    // json when None
+    pub format: Option<MemoFormat>,
+}
+
+// the documents a DirStore wrote, one path relative to the directory per line
+const MANIFEST: &str = ".git-memo-manifest";
+
+// characters other than these are percent-encoded in file names
+fn is_safe_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '.' || c == '_' || c == '-'
+}
+
+fn encode_component(component: &str) -> String {
+    // "." and ".." would leave the directory
+    if component.chars().all(|c| c == '.') {
+        return component.replace('.', "%2E");
+    }
+    let mut encoded = String::with_capacity(component.len());
+    for c in component.chars() {
+        if is_safe_char(c) {
+            encoded.push(c);
+        } else {
+            let mut bytes = [0; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    encoded
+}
+
+impl DirStore {
+    fn extension(&self) -> &'static str {
+        match self.format.unwrap_or(MemoFormat::Json) {
+            MemoFormat::Json => "json",
+            MemoFormat::Yaml => "yaml",
+        }
+    }
+
+    // <path>.json, or <repo>@/<path>.json for files of another repository,
+    // relative to dir; '@' of paths is encoded, so only the repository component ends with it
+    fn document_path(&self, file_data: &FileData) -> PathBuf {
+        let mut path = PathBuf::new();
+        if let Some(repo) = &file_data.repo {
+            path.push(format!("{}@", encode_component(repo)));
+        }
+        let components: Vec<&str> = file_data
+            .path
+            .split('/')
+            .filter(|component| !component.is_empty() && *component != ".")
+            .collect();
+        for (index, component) in components.iter().enumerate() {
+            let component = encode_component(component);
+            if index + 1 == components.len() {
+                path.push(format!("{}.{}", component, self.extension()));
+            } else {
+                path.push(component);
+            }
+        }
+        path
+    }
+
+    // the documents written by the last save, relative to dir; None when the
+    // directory was never written by a DirStore
+    fn read_manifest(&self) -> Result<Option<Vec<PathBuf>>> {
+        let manifest = Path::new(&self.dir).join(MANIFEST);
+        if !manifest.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&manifest)
+            .with_context(|| format!("failed to read {}", manifest.display()))?;
+        let mut documents = Vec::new();
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let document = PathBuf::from(line);
+            // entries are never followed out of dir or into .git
+            let inside = document
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+                && !document.starts_with(".git");
+            if !inside {
+                return Err(anyhow!("invalid entry {} in {}", line, manifest.display()));
+            }
+            documents.push(document);
+        }
+        Ok(Some(documents))
+    }
+
+    fn write_manifest(&self, documents: &[&PathBuf]) -> Result<()> {
+        let manifest = Path::new(&self.dir).join(MANIFEST);
+        let temp = Path::new(&self.dir).join(format!("{}.tmp", MANIFEST));
+        let contents: String = documents
+            .iter()
+            .map(|document| format!("{}\n", document.to_string_lossy()))
+            .collect();
+        std::fs::write(&temp, contents)
+            .and_then(|_| std::fs::rename(&temp, &manifest))
+            .with_context(|| format!("failed to write {}", manifest.display()))
+    }
+}
+
+impl MemoStore for DirStore {
+    fn load(&self) -> Result<RootData> {
+        let documents = self.read_manifest()?.with_context(|| {
+            format!(
+                "{} has no {}, so it was not written by --output-dir",
+                self.dir, MANIFEST
+            )
+        })?;
+        let parts = documents
+            .iter()
+            .map(|document| {
+                let document = Path::new(&self.dir).join(document);
+                load_root_data(&document.to_string_lossy(), self.format)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(merge_root_data(parts).0)
+    }
+
+    fn save(&self, data: &RootData) -> Result<()> {
+        // files whose paths only differ by "./" share a document
+        let mut documents: BTreeMap<PathBuf, RootData> = BTreeMap::new();
+        for file_data in &data.files {
+            documents
+                .entry(self.document_path(file_data))
+                .or_insert_with(|| RootData {
+                    version: data.version,
+                    files: Vec::new(),
+                })
+                .files
+                .push(file_data.clone());
+        }
+        // only documents of the last save are ever overwritten or removed, so
+        // other files in the directory such as the --input are left alone
+        let written = self.read_manifest()?.unwrap_or_default();
+        for document in documents.keys() {
+            let path = Path::new(&self.dir).join(document);
+            if path.exists() && !written.contains(document) {
+                return Err(anyhow!(
+                    "refusing to overwrite {}, which was not written by --output-dir",
+                    path.display()
+                ));
+            }
+        }
+        for (document, document_data) in &documents {
+            let path = Path::new(&self.dir).join(document);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("failed to create {}", parent.display()))?;
+            }
+            save_root_data(&path.to_string_lossy(), document_data, self.format)?;
+        }
+        self.write_manifest(&documents.keys().collect::<Vec<_>>())?;
+        // documents of paths which are gone, e.g. after renames
+        for document in written
+            .iter()
+            .filter(|document| !documents.contains_key(*document))
+        {
+            let path = Path::new(&self.dir).join(document);
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(err).with_context(|| format!("failed to remove {}", path.display()))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
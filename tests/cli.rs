@@ -0,0 +1,408 @@
+mod common;
+
+use common::{numbered_lines, Repo};
+
+#[test]
+fn add_then_track_then_list() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["--input", "memo.json", "--output", "memo.json"])
+        .args(["add", "--file", "a.txt", "--line", "2", "--text", "two"])
+        .assert()
+        .success();
+    repo.write("a.txt", &format!("top\n{}", numbered_lines("line ", 5)));
+    repo.commit("c2");
+    repo.git_memo()
+        .args(["--input", "memo.json", "--output", "memo.json"])
+        .assert()
+        .success();
+    repo.git_memo()
+        .args(["--input", "memo.json", "list"])
+        .assert()
+        .success()
+        .stdout("a.txt:3: two\n");
+}
+
+#[test]
+fn output_dir_leaves_other_files_alone() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("in.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["--input", "in.json", "--output", "in.json"])
+        .args(["add", "--file", "a.txt", "--line", "2", "--text", "two"])
+        .assert()
+        .success();
+    let input = repo.read("in.json");
+    repo.write("keep.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["-q", "--input", "in.json", "--output-dir", "."])
+        .assert()
+        .success();
+    assert_eq!(repo.read("in.json"), input);
+    assert!(repo.exists("keep.json"));
+    assert!(repo.exists("a.txt.json"));
+
+    // memo files of renamed paths are removed, other files are not
+    repo.git(&["mv", "a.txt", "b.txt"]);
+    repo.commit("c2");
+    repo.git_memo()
+        .args(["-q", "--input", ".", "--output-dir", "."])
+        .assert()
+        .success();
+    assert!(!repo.exists("a.txt.json"));
+    assert!(repo.exists("b.txt.json"));
+    assert_eq!(repo.read("in.json"), input);
+    assert!(repo.exists("keep.json"));
+}
+
+#[test]
+fn output_dir_refuses_to_overwrite_other_files() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("in.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["--input", "in.json", "--output", "in.json"])
+        .args(["add", "--file", "a.txt", "--line", "2", "--text", "two"])
+        .assert()
+        .success();
+    repo.write("out/a.txt.json", "not a memo file");
+    repo.git_memo()
+        .args(["-q", "--input", "in.json", "--output-dir", "out"])
+        .assert()
+        .failure();
+    assert_eq!(repo.read("out/a.txt.json"), "not a memo file");
+}
+
+#[test]
+fn check_and_dry_run_keep_fuzzy_memos() {
+    let repo = Repo::new();
+    repo.write("a.rs", "fn main() {\n    let total = compute(1, 2);\n}\n");
+    repo.commit("c1");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["--input", "memo.json", "--output", "memo.json"])
+        .args(["add", "--file", "a.rs", "--line", "2", "--text", "fz"])
+        .assert()
+        .success();
+    repo.write("a.rs", "fn main() {\n    let total = compute(1, 3);\n}\n");
+    repo.commit("c2");
+    repo.git_memo()
+        .args([
+            "-q",
+            "--fuzz",
+            "2",
+            "--input",
+            "memo.json",
+            "--output",
+            "memo.json",
+        ])
+        .assert()
+        .success();
+    repo.git_memo()
+        .args(["--input", "memo.json", "--dry-run", "--fail-on-missing"])
+        .assert()
+        .success();
+    repo.git_memo()
+        .args(["--input", "memo.json", "--check"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn hunk_refers_to_lines_at_the_revision() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write(
+        "a.txt",
+        &format!("line 1\nx\ny\n{}", &numbered_lines("line ", 5)[7..]),
+    );
+    repo.commit("c2");
+    repo.write("a.txt", &format!("top\n{}", repo.read("a.txt")));
+    repo.commit("c3");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["--input", "memo.json", "--output", "memo.json"])
+        .args([
+            "add",
+            "--file",
+            "a.txt",
+            "--hunk",
+            "--revision",
+            "HEAD~1",
+            "--text",
+            "xy",
+        ])
+        .assert()
+        .success();
+    repo.git_memo()
+        .args(["-q", "--input", "memo.json", "--output", "memo.json"])
+        .assert()
+        .success();
+    repo.git_memo()
+        .args(["--input", "memo.json", "list"])
+        .assert()
+        .success()
+        .stdout("a.txt:3-4: xy\n");
+}
+
+// (revision, line, end_line, status) of the tag of the only memo of the memo file
+fn only_tag(repo: &Repo, memo_file: &str) -> (String, i64, Option<i64>, String) {
+    let data: serde_json::Value = serde_json::from_str(&repo.read(memo_file)).unwrap();
+    let tag = &data["files"][0]["comments"][0]["tags"][0];
+    (
+        tag["revision"].as_str().unwrap().to_string(),
+        tag["line"].as_i64().unwrap(),
+        tag["end_line"].as_i64(),
+        tag["status"].as_str().unwrap().to_string(),
+    )
+}
+
+#[test]
+fn hunk_takes_the_lines_of_uncommitted_changes() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    // staged and then unstaged changes, of which the worktree ones are the most recent
+    repo.write("a.txt", &format!("top\n{}", numbered_lines("line ", 5)));
+    repo.git(&["add", "a.txt"]);
+    repo.write(
+        "a.txt",
+        &format!("top\n{}x\ny\n", numbered_lines("line ", 5)),
+    );
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    let add = |text: &str| {
+        repo.git_memo()
+            .args(["--input", "memo.json", "--output", "memo.json"])
+            .args(["add", "--file", "a.txt", "--hunk", "--text", text])
+            .assert()
+            .success();
+    };
+    add("xy");
+    let zero = "0".repeat(40);
+    assert_eq!(
+        only_tag(&repo, "memo.json"),
+        (zero.clone(), 7, Some(8), String::from("Uncommitted"))
+    );
+
+    // with the worktree like the index, the staged hunk is taken
+    repo.git(&["checkout", "a.txt"]);
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    add("top");
+    assert_eq!(
+        only_tag(&repo, "memo.json"),
+        (zero, 1, None, String::from("Uncommitted"))
+    );
+}
+
+#[test]
+fn tracking_again_leaves_the_memo_file_as_it_is() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["--input", "memo.json", "--output", "memo.json"])
+        .args(["add", "--file", "a.txt", "--line", "2", "--text", "two"])
+        .assert()
+        .success();
+    repo.write("a.txt", &format!("top\n{}", numbered_lines("line ", 5)));
+    repo.commit("c2");
+    let track = || {
+        repo.git_memo()
+            .args(["-q", "--input", "memo.json", "--output", "memo.json"])
+            .assert()
+            .success();
+        repo.read("memo.json")
+    };
+    let first = track();
+    assert_eq!(track(), first);
+}
+
+#[test]
+fn porcelain_fields_decode_to_the_path_and_text() {
+    let decode = |field: &str| {
+        let mut bytes = Vec::new();
+        let mut rest = field.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            if byte == b'%' {
+                let hex = std::str::from_utf8(&tail[..2]).unwrap();
+                bytes.push(u8::from_str_radix(hex, 16).unwrap());
+                rest = &tail[2..];
+            } else {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+        String::from_utf8(bytes).unwrap()
+    };
+    let repo = Repo::new();
+    repo.write("a:b.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    let text = "100% sure: see\r\nthe next line";
+    repo.git_memo()
+        .args(["--input", "memo.json", "--output", "memo.json"])
+        .args(["add", "--file", "a:b.txt", "--line", "2", "--text", text])
+        .assert()
+        .success();
+    let output = repo
+        .git_memo()
+        .args(["--input", "memo.json", "list", "--porcelain"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let records: Vec<&str> = stdout.lines().collect();
+    assert_eq!(records.len(), 1, "{}", stdout);
+    let fields: Vec<&str> = records[0].split(':').collect();
+    assert_eq!(fields.len(), 5, "{}", records[0]);
+    assert_eq!(decode(fields[0]), "a:b.txt");
+    assert_eq!(fields[1], "2");
+    assert_eq!(fields[3], "Normal");
+    assert_eq!(decode(fields[4]), text);
+}
+
+#[test]
+fn max_width_cuts_multibyte_texts_on_character_boundaries() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    for (line, text) in [("1", "日本語のメモ"), ("2", "👍🏽👍🏽"), ("3", "a😀b")] {
+        repo.git_memo()
+            .args(["--input", "memo.json", "--output", "memo.json"])
+            .args(["add", "--file", "a.txt", "--line", line, "--text", text])
+            .assert()
+            .success();
+    }
+    repo.git_memo()
+        .args(["--input", "memo.json", "list", "--max-width", "3"])
+        .assert()
+        .success()
+        .stdout("a.txt:1: 日本…\na.txt:2: 👍🏽…\na.txt:3: a😀b\n");
+}
+
+#[test]
+fn list_status_missing_prints_the_memos_which_lost_their_lines() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    for (line, text) in [("2", "gone"), ("4", "kept")] {
+        repo.git_memo()
+            .args(["--input", "memo.json", "--output", "memo.json"])
+            .args(["add", "--file", "a.txt", "--line", line, "--text", text])
+            .assert()
+            .success();
+    }
+    repo.write("a.txt", "line 1\nline 3\nline 4\nline 5\n");
+    repo.commit("c2");
+    repo.git_memo()
+        .args(["-q", "--input", "memo.json", "--output", "memo.json"])
+        .assert()
+        .success();
+    repo.git_memo()
+        .args(["--input", "memo.json", "list", "--status", "Missing"])
+        .assert()
+        .success()
+        .stdout("a.txt:2: gone (Missing)\n");
+    repo.git_memo()
+        .args(["-q", "--input", "memo.json", "list"])
+        .assert()
+        .success()
+        .stdout("a.txt:3: kept\n");
+}
+
+#[test]
+fn tracking_flags_are_accepted_after_update() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["--input", "memo.json", "--output", "memo.json"])
+        .args(["add", "--file", "a.txt", "--line", "2", "--text", "two"])
+        .assert()
+        .success();
+    let added = repo.read("memo.json");
+    repo.write("a.txt", &format!("top\n{}", numbered_lines("line ", 5)));
+    repo.commit("c2");
+    repo.git_memo()
+        .args(["-q", "--input", "memo.json", "--output", "memo.json"])
+        .args(["update", "--dry-run", "a.txt"])
+        .assert()
+        .code(1);
+    assert_eq!(repo.read("memo.json"), added);
+    repo.git_memo()
+        .args(["-q", "--input", "memo.json", "--output", "memo.json"])
+        .args(["update", "--to", "HEAD", "--jobs", "1", "a.txt"])
+        .assert()
+        .success();
+    repo.git_memo()
+        .args(["--input", "memo.json", "list"])
+        .assert()
+        .success()
+        .stdout("a.txt:3: two\n");
+}
+
+#[test]
+fn tags_ahead_of_the_target_are_warned_about() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("a.txt", &format!("top\n{}", numbered_lines("line ", 5)));
+    repo.commit("c2");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["--input", "memo.json", "--output", "memo.json"])
+        .args(["add", "--file", "a.txt", "--line", "3", "--text", "two"])
+        .assert()
+        .success();
+    let output = repo
+        .git_memo()
+        .args([
+            "--input",
+            "memo.json",
+            "--output",
+            "out.json",
+            "--to",
+            "HEAD~1",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("is ahead of the target"), "{}", stderr);
+}
+
+#[test]
+fn memos_piped_through_stdin_are_tracked_to_stdout() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    repo.write("memo.json", r#"{"version":1,"files":[]}"#);
+    repo.git_memo()
+        .args(["--input", "memo.json", "--output", "memo.json"])
+        .args(["add", "--file", "a.txt", "--line", "2", "--text", "two"])
+        .assert()
+        .success();
+    repo.write("a.txt", &format!("top\n{}", numbered_lines("line ", 5)));
+    repo.commit("c2");
+    let output = repo
+        .git_memo()
+        .args(["-q", "--repo", ".", "-"])
+        .write_stdin(repo.read("memo.json"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let data: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let tags = &data["files"][0]["comments"][0]["tags"];
+    assert_eq!(tags[tags.as_array().unwrap().len() - 1]["line"], 3);
+    assert!(!repo.exists("out.json"));
+}
@@ -0,0 +1,125 @@
+// scratch repositories for the tests, removed when dropped
+#![allow(dead_code)]
+
+use git_memo::{add_memo, RootData, TagStatus, ROOT_DATA_VERSION};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+pub struct Repo {
+    dir: TempDir,
+}
+
+impl Repo {
+    pub fn new() -> Repo {
+        let repo = Repo {
+            dir: tempfile::tempdir().unwrap(),
+        };
+        repo.git(&["init", "-q"]);
+        repo.git(&["config", "user.name", "Test"]);
+        repo.git(&["config", "user.email", "test@example.com"]);
+        repo.git(&["config", "commit.gpgsign", "false"]);
+        repo
+    }
+
+    pub fn path(&self) -> &str {
+        self.dir.path().to_str().unwrap()
+    }
+
+    // trimmed stdout of a git command which must succeed
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(self.path())
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {}: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.dir.path().join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(path, contents).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        std::fs::read_to_string(self.dir.path().join(path)).unwrap()
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.dir.path().join(path).exists()
+    }
+
+    // commits everything and returns the full hash of the commit
+    pub fn commit(&self, message: &str) -> String {
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "-m", message]);
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    // git-memo run in the repository
+    pub fn git_memo(&self) -> assert_cmd::Command {
+        let mut command = assert_cmd::Command::cargo_bin("git-memo").unwrap();
+        command.current_dir(self.path());
+        command
+    }
+
+    // a memo at HEAD, stamped with its abbreviated hash like git-memo add does
+    pub fn add(&self, data: &mut RootData, file: &str, line: i32, text: &str) {
+        let revision = self.git(&["rev-parse", "--short", "HEAD"]);
+        add_memo(
+            self.path(),
+            data,
+            file,
+            line,
+            None,
+            text,
+            &revision,
+            BTreeMap::new(),
+            false,
+        )
+        .unwrap();
+    }
+
+    pub fn file_path(&self, path: &str) -> String {
+        Path::new(self.path())
+            .join(path)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+}
+
+pub fn empty_data() -> RootData {
+    RootData {
+        version: ROOT_DATA_VERSION,
+        files: Vec::new(),
+    }
+}
+
+// "<prefix>1\n<prefix>2\n..." up to n
+pub fn numbered_lines(prefix: &str, n: usize) -> String {
+    (1..=n).map(|i| format!("{}{}\n", prefix, i)).collect()
+}
+
+// (line, status) of the latest tag of the memo with the text
+pub fn latest(data: &RootData, text: &str) -> (i32, TagStatus) {
+    let comment = data
+        .files
+        .iter()
+        .flat_map(|file_data| &file_data.comments)
+        .find(|comment| comment.text == text)
+        .unwrap_or_else(|| panic!("no memo {}", text));
+    let tag = comment.tags.last().unwrap();
+    (tag.line, tag.status.clone())
+}
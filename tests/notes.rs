@@ -0,0 +1,98 @@
+mod common;
+
+use common::{numbered_lines, Repo};
+use git_memo::notes::GitNotesStore;
+use git_memo::runner::{FakeGit, GitOutput, SystemGit};
+use git_memo::store::MemoStore;
+use git_memo::RootData;
+
+#[test]
+fn memos_without_a_commit_of_the_repository_are_kept() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    let head = repo.commit("c1");
+    let data: RootData = serde_json::from_str(&format!(
+        r#"{{"version":1,"files":[
+            {{"path":"a.txt","comments":[
+                {{"text":"normal","tags":[{{"revision":"{head}","line":1,"status":"Normal"}}]}},
+                {{"text":"lost","tags":[
+                    {{"revision":"{head}","line":2,"status":"Normal"}},
+                    {{"revision":"abcdef1","line":2,"status":"Unknown"}}]}},
+                {{"text":"unknown","tags":[{{"revision":"abcdef1","line":3,"status":"Unknown"}}]}},
+                {{"text":"uncommitted","tags":[{{"revision":"0000000","line":4,"status":"Uncommitted"}}]}}]}},
+            {{"path":"b.txt","repo":"sub","comments":[
+                {{"text":"sub","tags":[{{"revision":"1234567","line":1,"status":"Normal"}}]}}]}}]}}"#
+    ))
+    .unwrap();
+    let store = GitNotesStore {
+        repo_path: repo.path().to_string(),
+        runner: &SystemGit,
+    };
+    store.save(&data).unwrap();
+
+    let loaded = store.load().unwrap();
+    let mut texts: Vec<&str> = loaded
+        .files
+        .iter()
+        .flat_map(|file_data| file_data.comments.iter())
+        .map(|comment| comment.text.as_str())
+        .collect();
+    texts.sort_unstable();
+    assert_eq!(texts, ["lost", "normal", "sub", "uncommitted", "unknown"]);
+    let sub = loaded
+        .files
+        .iter()
+        .find(|file_data| file_data.path == "b.txt");
+    assert_eq!(sub.unwrap().repo.as_deref(), Some("sub"));
+}
+
+#[test]
+fn notes_are_read_and_written_through_the_runner() {
+    let commit = "1111111111111111111111111111111111111111";
+    let note = format!(
+        r#"[{{"path":"a.txt","comment":{{"text":"memo","tags":[{{"revision":"{commit}","line":2,"status":"Normal"}}]}}}}]"#
+    );
+    let notes = |args: &[&str]| -> Vec<String> {
+        ["-C", "repo", "notes", "--ref", "git-memo"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect()
+    };
+    let respond = |git: FakeGit, args: &[&str], stdout: &str| {
+        let args = notes(args);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        git.respond(&args, GitOutput::new(0, stdout, ""))
+    };
+    let git = FakeGit::default().respond(
+        &["-C", "repo", "rev-parse", commit],
+        GitOutput::new(0, &format!("{}\n", commit), ""),
+    );
+    let git = respond(
+        git,
+        &["list"],
+        &format!("2222222222222222222222222222222222222222 {}\n", commit),
+    );
+    let git = respond(git, &["show", commit], &note);
+    let git = respond(git, &["add", "-f", "-F", "-", commit], "");
+    let store = GitNotesStore {
+        repo_path: String::from("repo"),
+        runner: &git,
+    };
+
+    let data = store.load().unwrap();
+    assert_eq!(data.files.len(), 1);
+    assert_eq!(data.files[0].path, "a.txt");
+    assert_eq!(data.files[0].comments[0].text, "memo");
+    store.save(&data).unwrap();
+
+    // the note is written back through stdin to the commit of its tag
+    let inputs = git.inputs();
+    assert_eq!(inputs.len(), 1);
+    assert_eq!(inputs[0].0, notes(&["add", "-f", "-F", "-", commit]));
+    let written: serde_json::Value = serde_json::from_slice(&inputs[0].1).unwrap();
+    assert_eq!(
+        written,
+        serde_json::from_str::<serde_json::Value>(&note).unwrap()
+    );
+}
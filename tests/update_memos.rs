@@ -0,0 +1,197 @@
+mod common;
+
+use common::{empty_data, latest, numbered_lines, Repo};
+use git_memo::{update_memos, TagStatus, UpdateOption, UpdateOptionBuilder};
+
+fn track(repo: &Repo, data: git_memo::RootData) -> git_memo::RootData {
+    update_memos(repo.path(), data, &default_option()).unwrap()
+}
+
+fn default_option() -> UpdateOption {
+    UpdateOptionBuilder::default().build().unwrap()
+}
+
+#[test]
+fn lines_inserted_above_move_memos_down() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 10));
+    repo.commit("c1");
+    let mut data = empty_data();
+    repo.add(&mut data, "a.txt", 3, "third");
+    repo.add(&mut data, "a.txt", 10, "last");
+
+    repo.write(
+        "a.txt",
+        &format!("new 1\nnew 2\n{}", numbered_lines("line ", 10)),
+    );
+    repo.commit("c2");
+    let data = track(&repo, data);
+    assert_eq!(latest(&data, "third"), (5, TagStatus::Normal));
+    assert_eq!(latest(&data, "last"), (12, TagStatus::Normal));
+}
+
+#[test]
+fn lines_deleted_above_move_memos_up() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 10));
+    repo.commit("c1");
+    let mut data = empty_data();
+    repo.add(&mut data, "a.txt", 8, "eighth");
+
+    let lines: Vec<String> = (1..=10)
+        .filter(|i| ![2, 4, 6].contains(i))
+        .map(|i| format!("line {}\n", i))
+        .collect();
+    repo.write("a.txt", &lines.concat());
+    repo.commit("c2");
+    let data = track(&repo, data);
+    assert_eq!(latest(&data, "eighth"), (5, TagStatus::Normal));
+}
+
+#[test]
+fn deleted_line_marks_the_memo_missing() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    let mut data = empty_data();
+    repo.add(&mut data, "a.txt", 3, "gone");
+    repo.add(&mut data, "a.txt", 4, "kept");
+
+    repo.write("a.txt", "line 1\nline 2\nline 4\nline 5\n");
+    repo.commit("c2");
+    let data = track(&repo, data);
+    assert_eq!(latest(&data, "gone").1, TagStatus::Missing);
+    assert_eq!(latest(&data, "kept"), (3, TagStatus::Normal));
+}
+
+#[test]
+fn memos_follow_several_commits() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 5));
+    repo.commit("c1");
+    let mut data = empty_data();
+    repo.add(&mut data, "a.txt", 5, "five");
+
+    repo.write("a.txt", &format!("top\n{}", numbered_lines("line ", 5)));
+    repo.commit("c2");
+    repo.write(
+        "a.txt",
+        &format!("top\ntop\n{}", numbered_lines("line ", 5)),
+    );
+    repo.commit("c3");
+    let data = track(&repo, data);
+    assert_eq!(latest(&data, "five"), (7, TagStatus::Normal));
+    // a second run from the new tags changes nothing
+    let again = track(&repo, data.clone());
+    assert_eq!(latest(&again, "five"), (7, TagStatus::Normal));
+    assert_eq!(
+        again.files[0].comments[0].tags.len(),
+        data.files[0].comments[0].tags.len()
+    );
+}
+
+#[test]
+fn memos_follow_renames() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 10));
+    repo.commit("c1");
+    let mut data = empty_data();
+    repo.add(&mut data, "a.txt", 6, "six");
+
+    repo.git(&["mv", "a.txt", "b.txt"]);
+    repo.write("b.txt", &format!("top\n{}", numbered_lines("line ", 10)));
+    repo.commit("c2");
+    let data = track(&repo, data);
+    assert_eq!(data.files[0].path, "b.txt");
+    assert_eq!(latest(&data, "six"), (7, TagStatus::Normal));
+}
+
+#[test]
+fn from_only_tracks_the_tag_placed_at_it() {
+    let repo = Repo::new();
+    repo.write("a.txt", &numbered_lines("line ", 10));
+    let c1 = repo.commit("c1");
+    let mut data = empty_data();
+    repo.add(&mut data, "a.txt", 5, "five");
+    repo.write(
+        "a.txt",
+        &format!("a\nb\nc\n{}", numbered_lines("line ", 10)),
+    );
+    repo.commit("c2");
+    let data = track(&repo, data);
+    assert_eq!(latest(&data, "five"), (8, TagStatus::Normal));
+
+    repo.write(
+        "a.txt",
+        &format!("a\nb\nc\nd\n{}", numbered_lines("line ", 10)),
+    );
+    repo.commit("c3");
+    let option = UpdateOptionBuilder::default()
+        .from(Some(c1))
+        .build()
+        .unwrap();
+    let tags_before = data.files[0].comments[0].tags.len();
+    let data = update_memos(repo.path(), data, &option).unwrap();
+    // the line of the tag at c2 is not read at c1
+    assert_eq!(data.files[0].comments[0].tags.len(), tags_before + 1);
+    assert_eq!(latest(&data, "five"), (9, TagStatus::Normal));
+}
+
+#[test]
+fn fuzzy_tags_are_not_lost_again_without_fuzz() {
+    let repo = Repo::new();
+    repo.write(
+        "a.rs",
+        "fn main() {\n    let total = compute(1, 2);\n    println!(\"{}\", total);\n}\n",
+    );
+    repo.commit("c1");
+    let mut data = empty_data();
+    repo.add(&mut data, "a.rs", 2, "fz");
+    repo.write(
+        "a.rs",
+        "fn main() {\n    let total = compute(1, 3);\n    println!(\"{}\", total);\n}\n",
+    );
+    repo.commit("c2");
+    let option = UpdateOptionBuilder::default()
+        .fuzz(Some(2))
+        .build()
+        .unwrap();
+    let data = update_memos(repo.path(), data, &option).unwrap();
+    assert_eq!(latest(&data, "fz"), (2, TagStatus::Fuzzy));
+
+    let again = track(&repo, data.clone());
+    assert_eq!(latest(&again, "fz"), (2, TagStatus::Fuzzy));
+    assert_eq!(
+        again.files[0].comments[0].tags.len(),
+        data.files[0].comments[0].tags.len()
+    );
+}
+
+#[test]
+fn memos_of_an_emptied_file_are_marked_missing() {
+    let repo = Repo::new();
+    repo.write("a.txt", "line 1\n");
+    repo.commit("c1");
+    let mut data = empty_data();
+    repo.add(&mut data, "a.txt", 1, "one");
+
+    repo.write("a.txt", "");
+    repo.commit("c2");
+    let data = track(&repo, data);
+    assert_eq!(latest(&data, "one").1, TagStatus::Missing);
+}
+
+#[test]
+fn empty_blame_output_marks_the_memo_missing() {
+    let repo = Repo::new();
+    repo.write("a.txt", "");
+    repo.commit("c1");
+    // a line the empty file does not have, so blame reports no lines
+    let mut data = empty_data();
+    repo.add(&mut data, "a.txt", 1, "one");
+
+    repo.write("b.txt", "");
+    repo.commit("c2");
+    let data = track(&repo, data);
+    assert_eq!(latest(&data, "one").1, TagStatus::Missing);
+}